@@ -0,0 +1,87 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{DiscordifyError, Result};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AudioCodec {
+    Aac,
+    /// Sounds far better than AAC or MP3 at the 32–64 kbps small targets end up with.
+    Opus,
+    Mp3,
+}
+
+impl AudioCodec {
+    pub const ALL: [AudioCodec; 3] = [AudioCodec::Aac, AudioCodec::Opus, AudioCodec::Mp3];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            AudioCodec::Aac => "aac",
+            AudioCodec::Opus => "opus",
+            AudioCodec::Mp3 => "mp3",
+        }
+    }
+
+    /// ffmpeg encoders for this codec, in order of preference.
+    pub fn encoder_names(self) -> &'static [&'static str] {
+        match self {
+            AudioCodec::Aac => &["libfdk_aac", "aac"],
+            // The native opus encoder is still experimental.
+            AudioCodec::Opus => &["libopus"],
+            AudioCodec::Mp3 => &["libmp3lame"],
+        }
+    }
+
+    // Extension of the intermediate audio file, which picks its muxer.
+    pub(crate) fn extension(self) -> &'static str {
+        match self {
+            AudioCodec::Aac => "aac",
+            AudioCodec::Opus => "opus",
+            AudioCodec::Mp3 => "mp3",
+        }
+    }
+}
+
+impl fmt::Display for AudioCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for AudioCodec {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "aac" | "libfdk_aac" => Ok(AudioCodec::Aac),
+            "opus" | "libopus" => Ok(AudioCodec::Opus),
+            "mp3" | "libmp3lame" | "lame" => Ok(AudioCodec::Mp3),
+            _ => {
+                let names: Vec<&str> = AudioCodec::ALL.iter().map(|codec| codec.name()).collect();
+                Err(format!("unknown audio codec `{s}`, expected one of: {}", names.join(", ")))
+            }
+        }
+    }
+}
+
+// Audio codecs the output container can carry, the default pick first.
+fn container_codecs(extension: &str) -> &'static [AudioCodec] {
+    match extension.to_ascii_lowercase().as_str() {
+        "webm" | "ogg" => &[AudioCodec::Opus],
+        "mkv" => &[AudioCodec::Opus, AudioCodec::Aac, AudioCodec::Mp3],
+        // mp4 and mov: AAC is what every Discord client plays inline.
+        _ => &[AudioCodec::Aac, AudioCodec::Opus, AudioCodec::Mp3],
+    }
+}
+
+pub(crate) fn resolve(audio_codec: Option<AudioCodec>, extension: &str) -> Result<AudioCodec> {
+    let allowed = container_codecs(extension);
+    match audio_codec {
+        None => Ok(allowed[0]),
+        Some(codec) if allowed.contains(&codec) => Ok(codec),
+        Some(codec) => Err(DiscordifyError::IncompatibleCodec {
+            codec: codec.name().to_string(),
+            container: extension.to_string(),
+        }),
+    }
+}
@@ -1,18 +1,26 @@
-use std::fs::File;
-use std::io::Read;
 use ffmpeg_next as ffmpeg;
 
+use std::fs;
+use std::panic;
 use std::path::{Path, PathBuf};
-use std::time::Instant;
+use std::thread;
 use ffmpeg::{codec, filter, frame, media};
 use ffmpeg_next::{format};
-use sha1::{Sha1, Digest};
+use tracing::{debug, info};
+
+use crate::audio_codec::AudioCodec;
+use crate::cancel::CancelToken;
+use crate::error::{DiscordifyError, Result};
+use crate::preset::Presets;
+use crate::progress::{Reporter, Stage, Tracker};
+use crate::size::ByteSize;
+use crate::trim::{self, TimeRange};
 
 fn filter(
     spec: &str,
     decoder: &codec::decoder::Audio,
     encoder: &codec::encoder::Audio,
-) -> Result<filter::Graph, ffmpeg::Error> {
+) -> Result<filter::Graph> {
     let mut filter = filter::Graph::new();
 
     let args = format!(
@@ -23,11 +31,11 @@ fn filter(
         decoder.channel_layout().bits()
     );
 
-    filter.add(&filter::find("abuffer").unwrap(), "in", &args)?;
-    filter.add(&filter::find("abuffersink").unwrap(), "out", "")?;
+    filter.add(&filter::find("abuffer").ok_or(ffmpeg::Error::FilterNotFound)?, "in", &args)?;
+    filter.add(&filter::find("abuffersink").ok_or(ffmpeg::Error::FilterNotFound)?, "out", "")?;
 
     {
-        let mut out = filter.get("out").unwrap();
+        let mut out = filter.get("out").ok_or(ffmpeg::Error::FilterNotFound)?;
 
         out.set_sample_format(encoder.format());
         out.set_channel_layout(encoder.channel_layout());
@@ -37,7 +45,7 @@ fn filter(
     filter.output("in", 0)?.input("out", 0)?.parse(spec)?;
     filter.validate()?;
 
-    println!("{}", filter.dump());
+    debug!("audio filter graph:\n{}", filter.dump());
 
     if let Some(codec) = encoder.codec() {
         if !codec
@@ -46,7 +54,7 @@ fn filter(
         {
             filter
                 .get("out")
-                .unwrap()
+                .ok_or(ffmpeg::Error::FilterNotFound)?
                 .sink()
                 .set_frame_size(encoder.frame_size());
         }
@@ -61,37 +69,49 @@ struct Transcoder {
     decoder: codec::decoder::Audio,
     encoder: codec::encoder::Audio,
     in_time_base: ffmpeg::Rational,
+    encoder_time_base: ffmpeg::Rational,
     out_time_base: ffmpeg::Rational,
-    last_log_time: Instant,
-    last_log_frame_count: usize,
-    starting_time: Instant,
     frame_count: usize,
-    actual_start_time: Instant,
+    progress: Tracker,
 }
 
 fn linear_scale(input: f32, input_min: f32, input_max: f32, output_min: f32, output_max: f32) -> f32 {
     (input - input_min) / (input_max - input_min) * (output_max - output_min) + output_min
 }
 
-fn transcoder<P: AsRef<Path> + ?Sized>(
+// 32 kbps at the smallest preset up to 128 kbps at the largest.
+pub fn audio_bit_rate(file_size: ByteSize, presets: &Presets) -> usize {
+    let (smallest, largest) = presets.limit_range();
+    let scaled:f32 = linear_scale(file_size.as_mb() as f32, smallest.as_mb() as f32, largest.as_mb() as f32, 32.0, 128.0);
+    let cool_bit_rate:f32 = scaled * 1000.0;
+    cool_bit_rate as usize
+}
+
+// What `bit_rate` comes to over `duration_secs`, close enough to budget the video with before
+// the audio is encoded. The size search makes up for the difference.
+pub fn expected_size(bit_rate: usize, duration_secs: f64) -> ByteSize {
+    ByteSize::from_bytes((bit_rate as f64 * duration_secs.max(0.0) / 8.0).ceil() as u64)
+}
+
+fn transcoder(
     ictx: &mut format::context::Input,
     octx: &mut format::context::Output,
-    path: &P,
+    audio_codec: AudioCodec,
     filter_spec: &str,
-    file_size: &f32,
-    actual_start_time: Instant,
-) -> Result<Transcoder, ffmpeg::Error> {
-    let input = match ictx.streams().best(media::Type::Audio) {
-        Some(stream) => stream,
-        None => {
-            eprintln!("Could not find audio stream.");
-            return Err(ffmpeg::Error::StreamNotFound);
-        }
-    };
+    bit_rate: usize,
+    progress: Tracker,
+) -> Result<Transcoder> {
+    let input = ictx
+        .streams()
+        .best(media::Type::Audio)
+        .ok_or(DiscordifyError::NoStream("audio"))?;
     let context = codec::context::Context::from_parameters(input.parameters())?;
     let mut decoder = context.decoder().audio()?;
-    let codec = ffmpeg::encoder::find(octx.format().codec(path, media::Type::Audio))
-        .expect("failed to find encoder")
+    let codec = audio_codec
+        .encoder_names()
+        .iter()
+        .find_map(|name| ffmpeg::encoder::find_by_name(name))
+        .ok_or_else(|| DiscordifyError::EncoderNotFound(audio_codec.encoder_names().join("/")))?
         .audio()?;
     let global = octx
         .format()
@@ -113,24 +133,32 @@ fn transcoder<P: AsRef<Path> + ?Sized>(
         encoder.set_flags(codec::flag::Flags::GLOBAL_HEADER);
     }
 
-    encoder.set_rate(decoder.rate() as i32);
+    // libopus only takes 48 kHz and a few lower rates, the filter graph resamples to whatever we pick.
+    let rate = match codec.rates() {
+        Some(rates) => {
+            let rates: Vec<i32> = rates.collect();
+            if rates.contains(&(decoder.rate() as i32)) {
+                decoder.rate() as i32
+            } else {
+                rates.iter().copied().max().unwrap_or(decoder.rate() as i32)
+            }
+        }
+        None => decoder.rate() as i32,
+    };
+    encoder.set_rate(rate);
     encoder.set_channel_layout(channel_layout);
     encoder.set_format(
         codec
             .formats()
-            .expect("unknown supported formats")
-            .next()
-            .unwrap(),
+            .and_then(|mut formats| formats.next())
+            .ok_or_else(|| DiscordifyError::EncoderNotFound(format!("{} has no supported sample formats", codec.name())))?,
     );
 
-    let scaled:f32 = linear_scale(file_size.to_owned(), 8.0, 500.0, 32.0, 128.0);
-    let cool_bit_rate:f32 = scaled * 1024.0;
-
-    encoder.set_bit_rate(cool_bit_rate as usize);
-    encoder.set_max_bit_rate(cool_bit_rate as usize);
+    encoder.set_bit_rate(bit_rate);
+    encoder.set_max_bit_rate(bit_rate);
 
-    encoder.set_time_base((1, decoder.rate() as i32));
-    output.set_time_base((1, decoder.rate() as i32));
+    encoder.set_time_base((1, rate));
+    output.set_time_base((1, rate));
 
     let encoder = encoder.open_as(codec)?;
     output.set_parameters(&encoder);
@@ -138,6 +166,7 @@ fn transcoder<P: AsRef<Path> + ?Sized>(
     let filter = filter(filter_spec, &decoder, &encoder)?;
 
     let in_time_base = decoder.time_base();
+    let encoder_time_base = encoder.time_base();
     let out_time_base = output.time_base();
 
     Ok(Transcoder {
@@ -146,156 +175,206 @@ fn transcoder<P: AsRef<Path> + ?Sized>(
         decoder,
         encoder,
         in_time_base,
+        encoder_time_base,
         out_time_base,
-        last_log_time: Instant::now(),
-        last_log_frame_count: 0,
-        starting_time: Instant::now(),
         frame_count: 0,
-        actual_start_time,
+        progress,
     })
 }
 
 impl Transcoder {
-    fn send_frame_to_encoder(&mut self, frame: &ffmpeg::Frame) {
-        self.encoder.send_frame(frame).unwrap();
+    fn send_frame_to_encoder(&mut self, frame: &ffmpeg::Frame) -> Result<()> {
+        self.encoder.send_frame(frame)?;
+        Ok(())
     }
 
-    fn send_eof_to_encoder(&mut self) {
-        self.encoder.send_eof().unwrap();
+    fn send_eof_to_encoder(&mut self) -> Result<()> {
+        self.encoder.send_eof()?;
+        Ok(())
     }
 
-    fn receive_and_process_encoded_packets(&mut self, octx: &mut format::context::Output) {
+    fn receive_and_process_encoded_packets(&mut self, octx: &mut format::context::Output) -> Result<()> {
         let mut encoded = ffmpeg::Packet::empty();
         while self.encoder.receive_packet(&mut encoded).is_ok() {
             encoded.set_stream(0);
-            encoded.rescale_ts(self.in_time_base, self.out_time_base);
-            encoded.write_interleaved(octx).unwrap();
+            encoded.rescale_ts(self.encoder_time_base, self.out_time_base);
+            encoded.write_interleaved(octx)?;
         }
+        Ok(())
     }
 
-    fn add_frame_to_filter(&mut self, frame: &ffmpeg::Frame) {
-        self.filter.get("in").unwrap().source().add(frame).unwrap();
+    fn add_frame_to_filter(&mut self, frame: &ffmpeg::Frame) -> Result<()> {
+        self.filter.get("in").ok_or(ffmpeg::Error::FilterNotFound)?.source().add(frame)?;
+        Ok(())
     }
 
-    fn flush_filter(&mut self) {
-        self.filter.get("in").unwrap().source().flush().unwrap();
+    fn flush_filter(&mut self) -> Result<()> {
+        self.filter.get("in").ok_or(ffmpeg::Error::FilterNotFound)?.source().flush()?;
+        Ok(())
     }
 
-    fn get_and_process_filtered_frames(&mut self, octx: &mut format::context::Output) {
+    fn get_and_process_filtered_frames(&mut self, octx: &mut format::context::Output) -> Result<()> {
         let mut filtered = frame::Audio::empty();
         while self
             .filter
             .get("out")
-            .unwrap()
+            .ok_or(ffmpeg::Error::FilterNotFound)?
             .sink()
             .frame(&mut filtered)
             .is_ok()
         {
-            self.send_frame_to_encoder(&filtered);
-            self.receive_and_process_encoded_packets(octx);
+            self.send_frame_to_encoder(&filtered)?;
+            self.receive_and_process_encoded_packets(octx)?;
         }
+        Ok(())
     }
 
-    fn send_packet_to_decoder(&mut self, packet: &ffmpeg::Packet) {
-        self.decoder.send_packet(packet).unwrap();
+    fn send_packet_to_decoder(&mut self, packet: &ffmpeg::Packet) -> Result<()> {
+        self.decoder.send_packet(packet)?;
+        Ok(())
     }
 
-    fn send_eof_to_decoder(&mut self) {
-        self.decoder.send_eof().unwrap();
+    fn send_eof_to_decoder(&mut self) -> Result<()> {
+        self.decoder.send_eof()?;
+        Ok(())
     }
 
-    fn receive_and_process_decoded_frames(&mut self, octx: &mut format::context::Output) {
+    fn receive_and_process_decoded_frames(&mut self, octx: &mut format::context::Output) -> Result<()> {
         let mut decoded = frame::Audio::empty();
         while self.decoder.receive_frame(&mut decoded).is_ok() {
             self.frame_count += 1;
             let timestamp = decoded.timestamp();
             decoded.set_pts(timestamp);
-            self.log_progress();
-            self.add_frame_to_filter(&decoded);
-            self.get_and_process_filtered_frames(octx);
+            self.progress.update(self.frame_count, trim::seconds(timestamp, self.in_time_base));
+            self.add_frame_to_filter(&decoded)?;
+            self.get_and_process_filtered_frames(octx)?;
         }
-    }
-
-    fn log_progress(&mut self) {
-        if self.frame_count - self.last_log_frame_count < 100 && self.last_log_time.elapsed().as_secs_f64() < 1.0 {
-            return;
-        }
-        let total_seconds = self.actual_start_time.elapsed().as_secs_f64() as u64;
-        let minutes = (total_seconds % 3600) / 60;
-        let seconds = total_seconds % 60;
-        let formatted_time = format!("{:02}:{:02}", minutes, seconds);
-        eprintln!(
-            "[RUST] AUDIO ELAPSED: \t{:8.2}s\tFRAMES: {:8}\tTIMESTAMP: \t{formatted_time}",
-            self.starting_time.elapsed().as_secs_f64(),
-            self.frame_count,
-        );
-        self.last_log_frame_count = self.frame_count;
-        self.last_log_time = Instant::now();
+        Ok(())
     }
 }
 
-pub async fn audio(input: &PathBuf, file_size: &f32, actual_start_time:Instant) -> Result<Option<PathBuf>, Box<dyn std::error::Error>> {
-    let mut ictx = format::input(&input).unwrap();
+pub fn audio(input: &PathBuf, dir: &Path, bit_rate: usize, audio_codec: AudioCodec, trim: Option<TimeRange>, reporter: &Reporter, cancel: &CancelToken) -> Result<Option<PathBuf>> {
+    let mut ictx = format::input(&input).map_err(|source| DiscordifyError::Probe { path: input.clone(), source })?;
 
-    match ictx.streams().best(media::Type::Audio) {
-        Some(stream) => stream,
-        None => {
-            eprintln!("Could not find audio stream.");
-            return Err(Box::from("Audio stream not found"));
-        }
-    };
-
-    let mut hasher = Sha1::new();
-    let mut file = File::open(input).unwrap();
-
-    let mut buffer = [0; 1024];
-    loop {
-        let bytes_read = file.read(&mut buffer).unwrap();
-        if bytes_read == 0 {
-            break;
-        }
-        hasher.update(&buffer[..bytes_read]);
+    if ictx.streams().best(media::Type::Audio).is_none() {
+        info!("Input has no audio stream, skipping audio");
+        return Ok(None);
     }
 
-    let result = hasher.finalize();
-    let input_file_name = result.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
-    
-    let output_path = Path::new(&input)
-        .parent()
-        .expect("input file must have a parent directory")
-        .join(input_file_name)
-        .with_extension("aac");
+    let output_path = dir
+        .join("audio")
+        .with_extension(audio_codec.extension());
 
     let output = output_path
         .to_str()
-        .expect("failed to convert output file path to string");
+        .ok_or_else(|| DiscordifyError::InvalidPath(output_path.clone()))?;
+
+    // atrim cuts on the exact sample, asetpts moves the kept part to start at zero.
+    let filter = match &trim {
+        Some(range) => format!("atrim=start={}:end={},asetpts=PTS-{}/TB", range.start, range.end, range.start),
+        None => "anull".to_owned(),
+    };
+    if let Some(range) = &trim {
+        trim::seek(&mut ictx, range)?;
+    }
 
-    let filter = "anull".to_owned();
+    let duration = match &trim {
+        Some(range) => range.duration(),
+        None => ictx.duration() as f64 / f64::from(ffmpeg::ffi::AV_TIME_BASE),
+    };
+    let start = trim.map_or(0.0, |range| range.start);
+    let progress = reporter.tracker(Stage::Audio, "AUDIO", start, duration.max(0.0), Some(output_path.clone()));
 
-    let mut octx = format::output(&output).unwrap();
-    let mut transcoder = transcoder(&mut ictx, &mut octx, &output, &filter, file_size, actual_start_time).unwrap();
+    let mut octx = format::output(&output)?;
+    let mut transcoder = transcoder(&mut ictx, &mut octx, audio_codec, &filter, bit_rate, progress)?;
 
     octx.set_metadata(ictx.metadata().to_owned());
-    octx.write_header().unwrap();
+    octx.write_header()?;
 
     for (stream, mut packet) in ictx.packets() {
+        cancel.check()?;
         if stream.index() == transcoder.stream {
+            if let (Some(range), Some(packet_secs)) = (&trim, trim::seconds(packet.pts(), stream.time_base()))
+                && packet_secs >= range.end
+            {
+                break;
+            }
             packet.rescale_ts(stream.time_base(), transcoder.in_time_base);
-            transcoder.send_packet_to_decoder(&packet);
-            transcoder.receive_and_process_decoded_frames(&mut octx);
+            transcoder.send_packet_to_decoder(&packet)?;
+            transcoder.receive_and_process_decoded_frames(&mut octx)?;
         }
     }
 
-    transcoder.send_eof_to_decoder();
-    transcoder.receive_and_process_decoded_frames(&mut octx);
+    transcoder.send_eof_to_decoder()?;
+    transcoder.receive_and_process_decoded_frames(&mut octx)?;
 
-    transcoder.flush_filter();
-    transcoder.get_and_process_filtered_frames(&mut octx);
+    transcoder.flush_filter()?;
+    transcoder.get_and_process_filtered_frames(&mut octx)?;
 
-    transcoder.send_eof_to_encoder();
-    transcoder.receive_and_process_encoded_packets(&mut octx);
+    transcoder.send_eof_to_encoder()?;
+    transcoder.receive_and_process_encoded_packets(&mut octx)?;
 
-    octx.write_trailer().unwrap();
+    octx.write_trailer()?;
 
     Ok(Option::from(output_path))
 }
+
+// The audio track of a job, encoded on a thread of its own while the video gets going.
+pub struct PendingAudio {
+    task: Option<thread::JoinHandle<Result<Option<PathBuf>>>>,
+    path: Option<PathBuf>,
+    expected_size: ByteSize,
+    // Stops the encode when the job fails or is cancelled before it's done.
+    cancel: CancelToken,
+}
+
+impl PendingAudio {
+    /// Runs `encode`, normally a call to [`audio`], with a token that is cancelled along with
+    /// `cancel` or when this is dropped before it's done.
+    pub fn start(
+        expected_size: ByteSize,
+        cancel: &CancelToken,
+        encode: impl FnOnce(&CancelToken) -> Result<Option<PathBuf>> + Send + 'static,
+    ) -> Self {
+        let cancel = cancel.child();
+        let task_cancel = cancel.clone();
+        let task = thread::spawn(move || encode(&task_cancel));
+        Self {
+            task: Some(task),
+            path: None,
+            expected_size,
+            cancel,
+        }
+    }
+
+    /// Waits for the encode the first time, then returns its output, `None` without audio.
+    pub fn finish(&mut self) -> Result<Option<&Path>> {
+        if let Some(task) = self.task.take() {
+            self.path = match task.join() {
+                Ok(result) => result?,
+                Err(panic) => panic::resume_unwind(panic),
+            };
+        }
+        Ok(self.path.as_deref())
+    }
+
+    /// The encoded size once it's done, the expected one until then.
+    pub fn size(&self) -> ByteSize {
+        match (&self.task, &self.path) {
+            (None, Some(path)) => fs::metadata(path).map_or(self.expected_size, |meta| ByteSize::from_bytes(meta.len())),
+            (None, None) => ByteSize::default(),
+            (Some(_), _) => self.expected_size,
+        }
+    }
+}
+
+impl Drop for PendingAudio {
+    // The encode writes into the job's temp folder, which mustn't go away underneath it. It
+    // notices the token at its next packet, so the wait is short.
+    fn drop(&mut self) {
+        if let Some(task) = self.task.take() {
+            self.cancel.cancel();
+            let _ = task.join();
+        }
+    }
+}
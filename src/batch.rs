@@ -0,0 +1,120 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use glob::Pattern;
+use tracing::warn;
+use discordify::{ByteSize, NameTemplate};
+
+// What a directory gets filtered down to when no --ext or --glob is given.
+const VIDEO_EXTENSIONS: [&str; 9] = ["mp4", "mkv", "mov", "webm", "avi", "m4v", "flv", "ts", "wmv"];
+
+pub struct Filter {
+    pub extensions: Vec<String>,
+    pub glob: Option<Pattern>,
+    pub recursive: bool,
+    /// Files named like this are results of an earlier run and get skipped.
+    pub naming: NameTemplate,
+}
+
+impl Filter {
+    pub fn matches(&self, path: &Path) -> bool {
+        let Some(file_name) = path.file_name().and_then(|name| name.to_str()) else {
+            return false;
+        };
+        // Don't pick up what an earlier run wrote into the same folder.
+        if self.naming.is_output_name(file_name) {
+            return false;
+        }
+        if let Some(glob) = &self.glob {
+            return glob.matches(file_name);
+        }
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+        match extension {
+            Some(extension) if self.extensions.is_empty() => VIDEO_EXTENSIONS.contains(&extension.as_str()),
+            Some(extension) => self.extensions.iter().any(|wanted| wanted.eq_ignore_ascii_case(&extension)),
+            None => false,
+        }
+    }
+}
+
+// Files are taken as given, directories are expanded to the files in them that pass the filter.
+pub fn collect_inputs(inputs: &[PathBuf], filter: &Filter) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for input in inputs {
+        if input.is_dir() {
+            collect_dir(input, filter, &mut files);
+        } else {
+            files.push(input.clone());
+        }
+    }
+    files
+}
+
+fn collect_dir(dir: &Path, filter: &Filter, files: &mut Vec<PathBuf>) {
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => {
+            warn!("Can't read {}: {}", dir.display(), e);
+            return;
+        }
+    };
+    let mut paths: Vec<PathBuf> = entries.filter_map(|entry| entry.ok().map(|entry| entry.path())).collect();
+    paths.sort();
+    for path in paths {
+        if path.is_dir() {
+            if filter.recursive {
+                collect_dir(&path, filter, files);
+            }
+        } else if filter.matches(&path) {
+            files.push(path);
+        }
+    }
+}
+
+pub struct Outcome {
+    pub name: String,
+    pub input_size: ByteSize,
+    pub elapsed: Duration,
+    pub result: Result<(ByteSize, u32), String>,
+}
+
+pub fn print_summary(outcomes: &[Outcome]) {
+    let name_width = outcomes
+        .iter()
+        .map(|outcome| outcome.name.len())
+        .max()
+        .unwrap_or(0)
+        .max("FILE".len());
+
+    println!();
+    println!("{:<name_width$}  {:>10}  {:>10}  {:>6}  {:>8}  STATUS", "FILE", "INPUT", "OUTPUT", "PASSES", "TIME");
+    for outcome in outcomes {
+        let time = format!("{:.1}s", outcome.elapsed.as_secs_f64());
+        let input = outcome.input_size.to_string();
+        match &outcome.result {
+            Ok((output_size, passes)) => println!(
+                "{:<name_width$}  {:>10}  {:>10}  {:>6}  {:>8}  ok",
+                outcome.name,
+                input,
+                output_size.to_string(),
+                passes,
+                time,
+            ),
+            Err(e) => println!(
+                "{:<name_width$}  {:>10}  {:>10}  {:>6}  {:>8}  failed: {}",
+                outcome.name,
+                input,
+                "-",
+                "-",
+                time,
+                e,
+            ),
+        }
+    }
+
+    let failed = outcomes.iter().filter(|outcome| outcome.result.is_err()).count();
+    println!("{} done, {} failed", outcomes.len() - failed, failed);
+}
@@ -0,0 +1,140 @@
+// Picks output frame rate and dimensions that leave the encoder enough bits per pixel to not turn to mush.
+
+use crate::video_codec::VideoCodec;
+
+const HEIGHT_LADDER: [u32; 8] = [2160, 1440, 1080, 720, 540, 480, 360, 240];
+const FRAME_RATE_LADDER: [f64; 2] = [60.0, 30.0];
+// Below this a part isn't worth watching, so split mode cuts more parts instead.
+const MIN_SHORT_SIDE: u32 = 480;
+
+// Rough bits per pixel per frame below which each codec starts falling apart.
+pub fn min_bits_per_pixel(video_codec: Option<VideoCodec>) -> f64 {
+    match video_codec {
+        Some(VideoCodec::Hevc) | Some(VideoCodec::Vp9) => 0.035,
+        Some(VideoCodec::Av1) | Some(VideoCodec::Av1Aom) | Some(VideoCodec::Av1Svt) | Some(VideoCodec::Av1Rav1e) => 0.03,
+        Some(VideoCodec::H264) | None => 0.05,
+    }
+}
+
+// Frame rate goes first: 144 fps down to 60 is far less visible than halving the resolution.
+pub fn output_frame_rate(
+    width: u32,
+    height: u32,
+    frame_rate: f64,
+    bit_rate: usize,
+    min_bits_per_pixel: f64,
+    max_fps: Option<f64>,
+) -> f64 {
+    if width == 0 || height == 0 || !frame_rate.is_finite() || frame_rate <= 0.0 {
+        return frame_rate;
+    }
+    if let Some(max_fps) = max_fps {
+        return frame_rate.min(max_fps);
+    }
+
+    let bits_per_pixel = |fps: f64| bit_rate as f64 / (width as f64 * height as f64 * fps);
+    if bits_per_pixel(frame_rate) >= min_bits_per_pixel {
+        return frame_rate;
+    }
+    let lowest = FRAME_RATE_LADDER[FRAME_RATE_LADDER.len() - 1];
+    FRAME_RATE_LADDER
+        .iter()
+        .copied()
+        // 59.94 isn't worth "reducing" to 60.
+        .filter(|&fps| fps < frame_rate * 0.95)
+        .find(|&fps| bits_per_pixel(fps) >= min_bits_per_pixel)
+        .unwrap_or(frame_rate.min(lowest))
+}
+
+// Lowest video bitrate that still keeps the short side at MIN_SHORT_SIDE and 30 fps.
+pub fn min_acceptable_bit_rate(width: u32, height: u32, frame_rate: f64, min_bits_per_pixel: f64) -> f64 {
+    let short_side = width.min(height).max(1);
+    let scale = (MIN_SHORT_SIDE as f64 / short_side as f64).min(1.0);
+    let frame_rate = if frame_rate.is_finite() && frame_rate > 0.0 { frame_rate.min(30.0) } else { 30.0 };
+    width as f64 * scale * height as f64 * scale * frame_rate * min_bits_per_pixel
+}
+
+pub fn output_size(
+    width: u32,
+    height: u32,
+    frame_rate: f64,
+    bit_rate: usize,
+    min_bits_per_pixel: f64,
+    max_height: Option<u32>,
+) -> (u32, u32) {
+    if width == 0 || height == 0 {
+        return (width, height);
+    }
+    let frame_rate = if frame_rate.is_finite() && frame_rate > 0.0 { frame_rate } else { 30.0 };
+
+    let scale = match max_height {
+        Some(max_height) => (max_height as f64 / height as f64).min(1.0),
+        None => {
+            // The ladder is about the short side so portrait clips get the same treatment.
+            let short_side = width.min(height);
+            let bits_per_pixel = |scale: f64| {
+                bit_rate as f64 / (width as f64 * scale * height as f64 * scale * frame_rate)
+            };
+            if bits_per_pixel(1.0) >= min_bits_per_pixel {
+                1.0
+            } else {
+                let smallest = HEIGHT_LADDER[HEIGHT_LADDER.len() - 1];
+                HEIGHT_LADDER
+                    .iter()
+                    .filter(|&&h| h < short_side)
+                    .map(|&h| h as f64 / short_side as f64)
+                    .find(|&scale| bits_per_pixel(scale) >= min_bits_per_pixel)
+                    .unwrap_or((smallest as f64 / short_side as f64).min(1.0))
+            }
+        }
+    };
+
+    if scale >= 1.0 {
+        return (width, height);
+    }
+    (even((width as f64 * scale).round() as u32), even((height as f64 * scale).round() as u32))
+}
+
+// yuv420p needs even dimensions.
+fn even(value: u32) -> u32 {
+    (value & !1).max(2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_the_size_when_the_bits_suffice() {
+        assert_eq!(output_size(1920, 1080, 30.0, 8_000_000, 0.05, None), (1920, 1080));
+    }
+
+    #[test]
+    fn steps_down_by_the_short_side() {
+        // 0.032 bits per pixel at 1080p30, 0.072 at 720p30.
+        assert_eq!(output_size(1920, 1080, 30.0, 2_000_000, 0.05, None), (1280, 720));
+        assert_eq!(output_size(1080, 1920, 30.0, 2_000_000, 0.05, None), (720, 1280));
+        assert_eq!(output_size(1920, 1080, 30.0, 1_000, 0.05, None), (426, 240));
+    }
+
+    #[test]
+    fn max_height_caps_with_even_dimensions() {
+        assert_eq!(output_size(1920, 1080, 30.0, 100_000_000, 0.05, Some(481)), (854, 480));
+        assert_eq!(output_size(1920, 1080, 30.0, 1_000, 0.05, Some(2160)), (1920, 1080));
+    }
+
+    #[test]
+    fn frame_rate_drops_to_the_ladder() {
+        // 0.032 bits per pixel at 1080p60, 0.064 at 1080p30.
+        assert_eq!(output_frame_rate(1920, 1080, 60.0, 4_000_000, 0.05, None), 30.0);
+        assert_eq!(output_frame_rate(1920, 1080, 144.0, 8_000_000, 0.05, None), 60.0);
+        assert_eq!(output_frame_rate(1920, 1080, 59.94, 1_000, 0.05, None), 30.0);
+        assert_eq!(output_frame_rate(1920, 1080, 24.0, 1_000, 0.05, None), 24.0);
+    }
+
+    #[test]
+    fn max_fps_caps_the_frame_rate() {
+        assert_eq!(output_frame_rate(1920, 1080, 60.0, 100_000_000, 0.05, Some(30.0)), 30.0);
+        assert_eq!(output_frame_rate(1920, 1080, 24.0, 100_000_000, 0.05, Some(30.0)), 24.0);
+    }
+}
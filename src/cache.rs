@@ -0,0 +1,225 @@
+use std::env;
+use std::fs::{self, File};
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+
+use crate::error::Result;
+use crate::job::TranscodeResult;
+use crate::size::{ByteSize, GB};
+
+const ENTRY_EXTENSION: &str = "entry";
+
+/// How big a cache gets unless told otherwise before the least recently used results go.
+pub const DEFAULT_MAX_SIZE: ByteSize = ByteSize::from_bytes(2 * GB);
+
+/// Finished outputs, keyed by the input's SHA-1 together with the target size and every setting
+/// that changes the encode, so asking for the same thing twice is a copy instead of a transcode.
+///
+/// Each entry is the output file `<key>.<ext>` plus a `<key>.entry` text file describing it,
+/// whose modification time is when the entry was last used.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cache {
+    dir: PathBuf,
+    max_size: Option<ByteSize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheEntry {
+    pub key: String,
+    pub path: PathBuf,
+    /// The input the output was made from, as it was called back then.
+    pub input: PathBuf,
+    pub target_size: ByteSize,
+    pub size: ByteSize,
+    pub passes: u32,
+    pub video_bit_rate: usize,
+    pub audio_bit_rate: usize,
+    pub last_used: SystemTime,
+}
+
+impl Cache {
+    /// A cache in `dir` capped at [`DEFAULT_MAX_SIZE`].
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self {
+            dir: dir.into(),
+            max_size: Some(DEFAULT_MAX_SIZE),
+        }
+    }
+
+    /// Prunes the least recently used results on every insert until the rest fits in
+    /// `max_size`, `None` lets the cache grow without limit.
+    pub fn with_max_size(mut self, max_size: Option<ByteSize>) -> Self {
+        self.max_size = max_size;
+        self
+    }
+
+    /// `$XDG_CACHE_HOME/discordify`, falling back to `~/.cache/discordify`.
+    pub fn default_location() -> Option<Self> {
+        let cache_dir = env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".cache")))?;
+        Some(Self::new(cache_dir.join("discordify")))
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The entry for `key`, marked as used. Entries with their output gone count as missing.
+    pub fn get(&self, key: &str) -> Option<CacheEntry> {
+        let entry_path = self.dir.join(key).with_extension(ENTRY_EXTENSION);
+        let entry = read_entry(&entry_path).ok()?;
+        if !entry.path.is_file() {
+            return None;
+        }
+        if let Ok(file) = File::options().write(true).open(&entry_path) {
+            let _ = file.set_modified(SystemTime::now());
+        }
+        Some(entry)
+    }
+
+    /// Stores a copy of the result's output under `key`, then prunes down to the size cap.
+    pub(crate) fn insert(&self, key: &str, input: &Path, target_size: ByteSize, result: &TranscodeResult) -> Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        let extension = result.output_path.extension().and_then(|extension| extension.to_str()).unwrap_or("mp4");
+        let path = self.dir.join(key).with_extension(extension);
+        fs::copy(&result.output_path, &path)?;
+        let contents = format!(
+            "file = {}\ninput = {}\ntarget = {}\nsize = {}\npasses = {}\nvideo_bit_rate = {}\naudio_bit_rate = {}\n",
+            path.file_name().unwrap_or_default().to_string_lossy(),
+            input.display(),
+            target_size.bytes(),
+            result.size.bytes(),
+            result.passes,
+            result.video_bit_rate,
+            result.audio_bit_rate,
+        );
+        // Written last, an entry without its description is ignored and pruned.
+        fs::write(self.dir.join(key).with_extension(ENTRY_EXTENSION), contents)?;
+        if let Some(max_size) = self.max_size {
+            self.prune(None, Some(max_size))?;
+        }
+        Ok(())
+    }
+
+    /// Every entry, least recently used first.
+    pub fn list(&self) -> Result<Vec<CacheEntry>> {
+        let read_dir = match fs::read_dir(&self.dir) {
+            Ok(read_dir) => read_dir,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(e) => return Err(e.into()),
+        };
+        let mut entries = Vec::new();
+        for dir_entry in read_dir {
+            let path = dir_entry?.path();
+            if path.extension().is_some_and(|extension| extension == ENTRY_EXTENSION) {
+                match read_entry(&path) {
+                    Ok(entry) => entries.push(entry),
+                    Err(e) => warn!("Skipping broken cache entry {}: {}", path.display(), e),
+                }
+            }
+        }
+        entries.sort_by_key(|entry| entry.last_used);
+        Ok(entries)
+    }
+
+    pub fn total_size(&self) -> Result<ByteSize> {
+        Ok(ByteSize::from_bytes(self.list()?.iter().map(|entry| entry.size.bytes()).sum()))
+    }
+
+    /// Removes entries not used for `max_age`, then the least recently used ones until the
+    /// rest fits in `max_size`. With neither, removes everything. Returns what was removed.
+    pub fn prune(&self, max_age: Option<Duration>, max_size: Option<ByteSize>) -> Result<Vec<CacheEntry>> {
+        let mut entries = self.list()?;
+        let mut removed = Vec::new();
+        if max_age.is_none() && max_size.is_none() {
+            removed.append(&mut entries);
+        }
+        if let Some(max_age) = max_age {
+            let now = SystemTime::now();
+            let (old, kept): (Vec<_>, Vec<_>) = entries
+                .into_iter()
+                .partition(|entry| now.duration_since(entry.last_used).unwrap_or_default() > max_age);
+            removed.extend(old);
+            entries = kept;
+        }
+        if let Some(max_size) = max_size {
+            let mut total: u64 = entries.iter().map(|entry| entry.size.bytes()).sum();
+            while total > max_size.bytes() && !entries.is_empty() {
+                let entry = entries.remove(0);
+                total -= entry.size.bytes();
+                removed.push(entry);
+            }
+        }
+
+        for entry in &removed {
+            fs::remove_file(self.dir.join(&entry.key).with_extension(ENTRY_EXTENSION))?;
+            match fs::remove_file(&entry.path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        remove_orphans(&self.dir)?;
+        Ok(removed)
+    }
+}
+
+fn read_entry(entry_path: &Path) -> io::Result<CacheEntry> {
+    let contents = fs::read_to_string(entry_path)?;
+    let value = |name: &str| {
+        contents
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .find(|(key, _)| key.trim() == name)
+            .map(|(_, value)| value.trim().to_string())
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, format!("missing `{name}`")))
+    };
+    let number = |name: &str| {
+        value(name)?
+            .parse::<u64>()
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, format!("`{name}` is not a number")))
+    };
+
+    let dir = entry_path.parent().unwrap_or(Path::new(""));
+    // Only ever a file next to the entry, whatever the entry says.
+    let file = value("file")?;
+    let file = Path::new(&file)
+        .file_name()
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "`file` is not a file name"))?;
+    Ok(CacheEntry {
+        key: entry_path.file_stem().unwrap_or_default().to_string_lossy().into_owned(),
+        path: dir.join(file),
+        input: PathBuf::from(value("input")?),
+        target_size: ByteSize::from_bytes(number("target")?),
+        size: ByteSize::from_bytes(number("size")?),
+        passes: number("passes")? as u32,
+        video_bit_rate: number("video_bit_rate")? as usize,
+        audio_bit_rate: number("audio_bit_rate")? as usize,
+        last_used: fs::metadata(entry_path)?.modified()?,
+    })
+}
+
+fn is_key(name: &str) -> bool {
+    name.len() == 40 && name.bytes().all(|byte| byte.is_ascii_hexdigit())
+}
+
+// Outputs whose entry never got written, from a run that died while storing it. Anything not
+// named like a key is left alone, in case the cache folder is shared with other files.
+fn remove_orphans(dir: &Path) -> io::Result<()> {
+    let read_dir = match fs::read_dir(dir) {
+        Ok(read_dir) => read_dir,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e),
+    };
+    for dir_entry in read_dir {
+        let path = dir_entry?.path();
+        let is_entry = path.extension().is_some_and(|extension| extension == ENTRY_EXTENSION);
+        let named_like_key = path.file_stem().and_then(|stem| stem.to_str()).is_some_and(is_key);
+        if named_like_key && !is_entry && path.is_file() && !path.with_extension(ENTRY_EXTENSION).exists() {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,61 @@
+use std::time::{Duration, SystemTime};
+use tracing::warn;
+use discordify::{ByteSize, CacheEntry, DiscordifyError};
+
+use crate::cli::{CacheArgs, CacheCommand};
+
+const DAY: u64 = 24 * 60 * 60;
+
+pub fn run(args: &CacheArgs) -> Result<(), DiscordifyError> {
+    let Some(cache) = args.cache() else {
+        warn!("No cache folder, set $HOME or pass --cache-dir");
+        return Ok(());
+    };
+
+    match &args.command {
+        CacheCommand::List => {
+            let entries = cache.list()?;
+            if !entries.is_empty() {
+                print_entries(&entries);
+            }
+            let total: u64 = entries.iter().map(|entry| entry.size.bytes()).sum();
+            println!("{} results, {} in {}", entries.len(), ByteSize::from_bytes(total), cache.dir().display());
+        }
+        CacheCommand::Prune(prune) => {
+            let max_age = prune.older_than.map(|days| Duration::from_secs(days * DAY));
+            let removed = cache.prune(max_age, prune.max_size)?;
+            let freed: u64 = removed.iter().map(|entry| entry.size.bytes()).sum();
+            println!(
+                "Removed {} results ({}), {} left",
+                removed.len(),
+                ByteSize::from_bytes(freed),
+                cache.total_size()?,
+            );
+        }
+    }
+    Ok(())
+}
+
+fn print_entries(entries: &[CacheEntry]) {
+    println!("{:<8}  {:>9}  {:>10}  {:>10}  INPUT", "KEY", "LAST USED", "TARGET", "SIZE");
+    for entry in entries {
+        println!(
+            "{:<8}  {:>9}  {:>10}  {:>10}  {}",
+            &entry.key[..entry.key.len().min(8)],
+            ago(entry.last_used),
+            entry.target_size.to_string(),
+            entry.size.to_string(),
+            entry.input.display(),
+        );
+    }
+}
+
+fn ago(time: SystemTime) -> String {
+    let secs = SystemTime::now().duration_since(time).unwrap_or_default().as_secs();
+    match secs {
+        0..60 => "just now".to_string(),
+        60..3600 => format!("{}m ago", secs / 60),
+        3600..DAY => format!("{}h ago", secs / 3600),
+        _ => format!("{}d ago", secs / DAY),
+    }
+}
@@ -0,0 +1,45 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::error::{DiscordifyError, Result};
+
+/// Stops a running job from another thread or task. Clones share the flag, so keep one and
+/// hand another to [`TranscodeOptions::cancel`](crate::TranscodeOptions::cancel).
+///
+/// The job notices between packets, removes its intermediate files and returns
+/// [`DiscordifyError::Cancelled`]. Nothing is written to the output until the last pass is done,
+/// so a cancelled job leaves no partial output behind.
+#[derive(Debug, Clone, Default)]
+pub struct CancelToken {
+    cancelled: Arc<AtomicBool>,
+    parent: Option<Box<CancelToken>>,
+}
+
+impl CancelToken {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Relaxed);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Relaxed) || self.parent.as_ref().is_some_and(|parent| parent.is_cancelled())
+    }
+
+    /// A token that is cancelled along with this one, but can also be cancelled on its own.
+    pub(crate) fn child(&self) -> Self {
+        Self {
+            cancelled: Arc::default(),
+            parent: Some(Box::new(self.clone())),
+        }
+    }
+
+    pub(crate) fn check(&self) -> Result<()> {
+        match self.is_cancelled() {
+            true => Err(DiscordifyError::Cancelled),
+            false => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,440 @@
+use std::path::PathBuf;
+use clap::{ArgAction, CommandFactory, Parser, Subcommand, ValueEnum};
+use clap::error::ErrorKind;
+use glob::Pattern;
+use discordify::{AudioCodec, ByteSize, Cache, Clobber, DiscordifyError, NameTemplate, Presets, Profile, ProgressHandler, TranscodeOptions, VideoCodec};
+
+#[derive(Parser, Debug)]
+#[command(
+    name = "discordify",
+    version,
+    about = "Transcode videos to fit under a specific file size",
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+pub struct Args {
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
+    /// Video files or directories to transcode
+    #[arg(required = true)]
+    pub inputs: Vec<PathBuf>,
+
+    /// Only take files with these extensions from directories (defaults to common video extensions)
+    #[arg(long = "ext", value_delimiter = ',')]
+    pub extensions: Vec<String>,
+
+    /// Only take files whose name matches this pattern from directories, e.g. "*.mkv"
+    #[arg(long, conflicts_with = "extensions")]
+    pub glob: Option<Pattern>,
+
+    /// Also look in subdirectories
+    #[arg(short, long)]
+    pub recursive: bool,
+
+    /// Start of the part to keep, in seconds or [hh:]mm:ss
+    #[arg(long, value_parser = parse_time)]
+    pub start: Option<f64>,
+
+    /// End of the part to keep, in seconds or [hh:]mm:ss
+    #[arg(long, value_parser = parse_time, conflicts_with = "duration")]
+    pub end: Option<f64>,
+
+    /// How much to keep after --start, in seconds or [hh:]mm:ss
+    #[arg(short = 't', long, value_parser = parse_time)]
+    pub duration: Option<f64>,
+
+    /// Split into part1, part2, ... when the video is too long to look acceptable in one file
+    #[arg(long)]
+    pub split: bool,
+
+    /// Split into parts of this length, in seconds or [hh:]mm:ss
+    #[arg(long, value_parser = parse_time, conflicts_with = "split")]
+    pub split_every: Option<f64>,
+
+    /// Where to write the result (defaults to --name in --output-dir)
+    #[arg(short, long, conflicts_with_all = ["output_dir", "name"])]
+    pub output: Option<PathBuf>,
+
+    /// Folder to write the results to (defaults to each input's folder)
+    #[arg(long)]
+    pub output_dir: Option<PathBuf>,
+
+    #[command(flatten)]
+    pub transcode: TranscodeArgs,
+
+    /// Print what would be done without transcoding anything
+    #[arg(long)]
+    pub dry_run: bool,
+
+    #[command(flatten)]
+    pub log: LogArgs,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct LogArgs {
+    /// Only print errors
+    #[arg(short, long, global = true, conflicts_with = "verbose")]
+    pub quiet: bool,
+
+    /// Print more detail, -vv for everything including ffmpeg's own log
+    #[arg(short, long, global = true, action = ArgAction::Count)]
+    pub verbose: u8,
+
+    /// Also write the log to this file, with at least -v detail
+    #[arg(long, global = true)]
+    pub log_file: Option<PathBuf>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Watch a folder and transcode every recording that shows up in it
+    Watch(Box<WatchArgs>),
+    /// Look at or clean up the cache of finished results
+    Cache(CacheArgs),
+}
+
+#[derive(clap::Args, Debug)]
+pub struct WatchArgs {
+    /// Folder to watch. Files already in it that haven't been processed yet get picked up too
+    pub dir: PathBuf,
+
+    /// Folder to write the results to (defaults to a `discord` folder inside the watched one)
+    #[arg(long)]
+    pub into: Option<PathBuf>,
+
+    /// Only take files with these extensions (defaults to common video extensions)
+    #[arg(long = "ext", value_delimiter = ',')]
+    pub extensions: Vec<String>,
+
+    /// Only take files whose name matches this pattern, e.g. "*.mkv"
+    #[arg(long, conflicts_with = "extensions")]
+    pub glob: Option<Pattern>,
+
+    /// Seconds a file has to stay the same size before it counts as finished
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u64).range(1..))]
+    pub settle: u64,
+
+    #[command(flatten)]
+    pub transcode: TranscodeArgs,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct CacheArgs {
+    /// Cache folder to use instead of ~/.cache/discordify
+    #[arg(long, global = true)]
+    pub cache_dir: Option<PathBuf>,
+
+    #[command(subcommand)]
+    pub command: CacheCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum CacheCommand {
+    /// List the cached results, least recently used first
+    List,
+    /// Remove cached results
+    Prune(PruneArgs),
+}
+
+#[derive(clap::Args, Debug)]
+#[group(required = true, multiple = true)]
+pub struct PruneArgs {
+    /// Remove results that haven't been used for this many days
+    #[arg(long, value_name = "DAYS")]
+    pub older_than: Option<u64>,
+
+    /// Then remove the least recently used results until the cache is at most this big
+    #[arg(long, value_name = "SIZE")]
+    pub max_size: Option<ByteSize>,
+
+    /// Remove everything
+    #[arg(long, conflicts_with_all = ["older_than", "max_size"])]
+    pub all: bool,
+}
+
+impl CacheArgs {
+    pub fn cache(&self) -> Option<Cache> {
+        cache_at(&self.cache_dir)
+    }
+}
+
+/// Options shared by every way of running a transcode.
+#[derive(clap::Args, Debug)]
+pub struct TranscodeArgs {
+    /// Target file size (25, 25MB, 24.5M, 25MiB, 26214400B; a bare number is MB), or a Discord tier:
+    /// free, basic, nitro, server-boost-2 or server-boost-3
+    #[arg(short, long, default_value = "free", value_parser = parse_size)]
+    pub size: Size,
+
+    /// Stay this many percent under the target size (defaults to the preset table's margin for tiers, 0 otherwise)
+    #[arg(long, value_parser = parse_margin)]
+    pub margin: Option<f64>,
+
+    /// Preset table to use instead of ~/.config/discordify/presets
+    #[arg(long)]
+    pub presets: Option<PathBuf>,
+
+    /// Video codec: h264, hevc, vp9, av1, av1-aom, av1-svt or av1-rav1e (defaults to h264, vp9 for .webm output, or the input's codec with --profile source)
+    #[arg(short = 'c', long, visible_alias = "codec")]
+    pub video_codec: Option<VideoCodec>,
+
+    /// Audio codec: aac, opus or mp3 (defaults to aac, or opus for .webm output)
+    #[arg(short = 'a', long)]
+    pub audio_codec: Option<AudioCodec>,
+
+    /// Output profile: embed-safe (plays inline in Discord) or source (keep the input's codec and pixel format)
+    #[arg(long, default_value_t = Profile::EmbedSafe)]
+    pub profile: Profile,
+
+    /// Never output taller than this many pixels (disables picking the resolution from the bitrate)
+    #[arg(long, value_parser = clap::value_parser!(u32).range(2..))]
+    pub max_height: Option<u32>,
+
+    /// Never output more than this many frames per second (disables picking the frame rate from the bitrate)
+    #[arg(long, value_parser = parse_fps)]
+    pub max_fps: Option<f64>,
+
+    /// Give up after this many passes if the output is still too big
+    #[arg(long, default_value_t = 5, value_parser = clap::value_parser!(u32).range(1..))]
+    pub max_passes: u32,
+
+    /// Output file name: {stem} and {ext} are the input's name and extension, {size} the target in MB
+    #[arg(long, default_value_t = NameTemplate::default())]
+    pub name: NameTemplate,
+
+    /// Replace the output if it already exists
+    #[arg(long, conflicts_with = "no_clobber")]
+    pub overwrite: bool,
+
+    /// Skip inputs whose output already exists
+    #[arg(long)]
+    pub no_clobber: bool,
+
+    /// Folder for intermediate files (defaults to the system temp folder, $TMPDIR)
+    #[arg(long)]
+    pub temp_dir: Option<PathBuf>,
+
+    /// Leave the intermediate files in the temp folder instead of removing them
+    #[arg(long)]
+    pub keep_temp: bool,
+
+    /// Always transcode, even when the same input and settings are in the cache. Also skips
+    /// reading the whole input to hash it, which only the cache needs
+    #[arg(long)]
+    pub no_cache: bool,
+
+    /// Cache folder to use instead of ~/.cache/discordify
+    #[arg(long, conflicts_with = "no_cache")]
+    pub cache_dir: Option<PathBuf>,
+
+    /// Keep the cache at most this big, dropping the least recently used results (defaults to 2GB)
+    #[arg(long, value_name = "SIZE", conflicts_with = "no_cache")]
+    pub cache_max_size: Option<ByteSize>,
+
+    /// Don't play the sound when done
+    #[arg(long)]
+    pub no_sound: bool,
+
+    /// Don't copy the result to the clipboard
+    #[arg(long)]
+    pub no_clipboard: bool,
+
+    /// How to report progress: text lines on stderr, or one JSON event per line on stdout
+    #[arg(long, value_enum, value_name = "FORMAT", default_value_t = ProgressFormat::Text)]
+    pub progress: ProgressFormat,
+
+}
+
+impl TranscodeArgs {
+    pub fn presets(&self) -> Result<Presets, DiscordifyError> {
+        match &self.presets {
+            Some(path) => Presets::load(path),
+            None => Presets::load_default(),
+        }
+    }
+
+    pub fn target_size(&self, presets: &Presets) -> Result<ByteSize, DiscordifyError> {
+        match &self.size {
+            Size::Bytes(size) => Ok(*size),
+            Size::Preset(name) => presets.limit(name),
+        }
+    }
+
+    // An exact size is taken as meant, a tier's limit gets the table's margin unless told otherwise.
+    fn margin(&self, presets: &Presets) -> f64 {
+        match (self.margin, &self.size) {
+            (Some(margin), _) => margin,
+            (None, Size::Bytes(_)) => 0.0,
+            (None, Size::Preset(_)) => presets.margin(),
+        }
+    }
+
+    fn clobber(&self) -> Clobber {
+        match (self.overwrite, self.no_clobber) {
+            (true, _) => Clobber::Overwrite,
+            (_, true) => Clobber::Skip,
+            _ => Clobber::Refuse,
+        }
+    }
+
+    fn cache(&self) -> Option<Cache> {
+        let cache = cache_at(&self.cache_dir)?;
+        Some(match self.cache_max_size {
+            Some(max_size) => cache.with_max_size(Some(max_size)),
+            None => cache,
+        })
+    }
+
+    pub fn options(&self, presets: Presets) -> TranscodeOptions {
+        TranscodeOptions {
+            margin: self.margin(&presets),
+            video_codec: self.video_codec,
+            audio_codec: self.audio_codec,
+            profile: self.profile,
+            max_passes: self.max_passes,
+            max_height: self.max_height,
+            max_fps: self.max_fps,
+            naming: self.name.clone(),
+            clobber: self.clobber(),
+            temp_dir: self.temp_dir.clone(),
+            keep_temp: self.keep_temp,
+            cache: if self.no_cache { None } else { self.cache() },
+            progress: (self.progress == ProgressFormat::Json).then(|| ProgressHandler::new(crate::events::progress)),
+            presets,
+            ..TranscodeOptions::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, ValueEnum)]
+pub enum ProgressFormat {
+    Text,
+    Json,
+}
+
+fn cache_at(dir: &Option<PathBuf>) -> Option<Cache> {
+    match dir {
+        Some(dir) => Some(Cache::new(dir)),
+        None => Cache::default_location(),
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Size {
+    Bytes(ByteSize),
+    /// Looked up in the preset table once it's loaded.
+    Preset(String),
+}
+
+fn parse_size(s: &str) -> Result<Size, String> {
+    if !s.starts_with(|c: char| c.is_ascii_digit() || c == '.' || c == '-') {
+        return Ok(Size::Preset(s.to_ascii_lowercase()));
+    }
+    s.parse().map(Size::Bytes)
+}
+
+// Percent, with or without the sign: 5 and 5% are both 0.05.
+fn parse_margin(s: &str) -> Result<f64, String> {
+    let percent: f64 = s
+        .trim_end_matches('%')
+        .parse()
+        .map_err(|_| format!("`{s}` is not a percentage"))?;
+    if !(0.0..100.0).contains(&percent) {
+        return Err(format!("margin must be at least 0 and below 100, got {s}"));
+    }
+    Ok(percent / 100.0)
+}
+
+fn parse_fps(s: &str) -> Result<f64, String> {
+    let fps: f64 = s.parse().map_err(|_| format!("`{s}` is not a number"))?;
+    if !fps.is_finite() || fps <= 0.0 {
+        return Err(format!("frame rate must be greater than 0, got {s}"));
+    }
+    Ok(fps)
+}
+
+// Accepts plain seconds (90, 12.5) as well as mm:ss and hh:mm:ss.
+fn parse_time(s: &str) -> Result<f64, String> {
+    let mut secs = 0.0;
+    let parts: Vec<&str> = s.split(':').collect();
+    if parts.len() > 3 {
+        return Err(format!("`{s}` is not a time, expected seconds or [hh:]mm:ss"));
+    }
+    for (i, part) in parts.iter().enumerate() {
+        let value: f64 = part.parse().map_err(|_| format!("`{s}` is not a time, expected seconds or [hh:]mm:ss"))?;
+        let is_last = i == parts.len() - 1;
+        if !value.is_finite() || value < 0.0 || (!is_last && value.fract() != 0.0) || (i > 0 && value >= 60.0) {
+            return Err(format!("`{s}` is not a time, expected seconds or [hh:]mm:ss"));
+        }
+        secs = secs * 60.0 + value;
+    }
+    Ok(secs)
+}
+
+impl Args {
+    pub fn parse_and_validate() -> Self {
+        let args = Self::parse();
+
+        match &args.command {
+            Some(Command::Watch(watch)) => {
+                if !watch.dir.is_dir() {
+                    Self::command()
+                        .error(ErrorKind::ValueValidation, format!("`{}` is not a folder", watch.dir.display()))
+                        .exit();
+                }
+                return args;
+            }
+            Some(Command::Cache(_)) => return args,
+            None => {}
+        }
+
+        for input in &args.inputs {
+            if !input.exists() {
+                Self::command()
+                    .error(ErrorKind::ValueValidation, format!("input `{}` does not exist", input.display()))
+                    .exit();
+            }
+        }
+
+        if args.output.is_some() && (args.inputs.len() > 1 || args.inputs[0].is_dir()) {
+            Self::command()
+                .error(ErrorKind::ArgumentConflict, "--output can only be used with a single input")
+                .exit();
+        }
+
+        args
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(flags: &[&str]) -> Args {
+        Args::try_parse_from(["discordify", "clip.mp4"].iter().chain(flags)).unwrap()
+    }
+
+    #[test]
+    fn parses_seconds_and_clock_times() {
+        assert_eq!(parse_time("90"), Ok(90.0));
+        assert_eq!(parse_time("12.5"), Ok(12.5));
+        assert_eq!(parse_time("1:30"), Ok(90.0));
+        assert_eq!(parse_time("01:02:03.5"), Ok(3723.5));
+    }
+
+    #[test]
+    fn rejects_what_is_not_a_time() {
+        for s in ["", "abc", "-5", "1:60", "1.5:00", "1:2:3:4", "inf"] {
+            assert!(parse_time(s).is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn clobber_follows_the_flags() {
+        assert_eq!(args(&[]).transcode.clobber(), Clobber::Refuse);
+        assert_eq!(args(&["--overwrite"]).transcode.clobber(), Clobber::Overwrite);
+        assert_eq!(args(&["--no-clobber"]).transcode.clobber(), Clobber::Skip);
+        assert!(Args::try_parse_from(["discordify", "clip.mp4", "--overwrite", "--no-clobber"]).is_err());
+    }
+}
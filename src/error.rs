@@ -0,0 +1,82 @@
+use std::path::PathBuf;
+use crate::size::ByteSize;
+use ffmpeg_next as ffmpeg;
+use thiserror::Error;
+
+#[derive(Debug, Error)]
+pub enum DiscordifyError {
+    #[error("could not open or probe {path}: {source}")]
+    Probe {
+        path: PathBuf,
+        source: ffmpeg::Error,
+    },
+
+    #[error("no {0} stream found in input")]
+    NoStream(&'static str),
+
+    #[error("{} doesn't report how long it is, so there's no bitrate to aim for", .0.display())]
+    UnknownDuration(PathBuf),
+
+    #[error("encoder not found: {0}")]
+    EncoderNotFound(String),
+
+    #[error("invalid encoder options: {0}")]
+    InvalidOptions(String),
+
+    #[error("path is not valid UTF-8: {}", .0.display())]
+    InvalidPath(PathBuf),
+
+    #[error("{} has no {part}", .path.display())]
+    IncompletePath {
+        path: PathBuf,
+        part: &'static str,
+    },
+
+    #[error("invalid preset: {0}")]
+    InvalidPreset(String),
+
+    #[error("invalid time range: {0}")]
+    InvalidTrim(String),
+
+    #[error("invalid split: {0}")]
+    InvalidSplit(String),
+
+    #[error("splitting would make {parts} parts, more than the {max} allowed")]
+    TooManyParts {
+        parts: u64,
+        max: u64,
+    },
+
+    #[error("{} already exists, pass --overwrite to replace it or --no-clobber to skip it", .0.display())]
+    OutputExists(PathBuf),
+
+    #[error("{codec} can't be stored in a .{container} file")]
+    IncompatibleCodec {
+        codec: String,
+        container: String,
+    },
+
+    #[error("can't reach {wanted}, audio alone needs {audio}")]
+    SizeTargetUnreachable {
+        wanted: ByteSize,
+        audio: ByteSize,
+    },
+
+    #[error("couldn't get below {wanted} within {passes} passes, best was {best}")]
+    SizeTargetNotReached {
+        wanted: ByteSize,
+        best: ByteSize,
+        passes: u32,
+    },
+
+    #[error("cancelled")]
+    Cancelled,
+
+    #[error("ffmpeg error: {0}")]
+    Ffmpeg(#[from] ffmpeg::Error),
+
+    #[error("io error: {0}")]
+    Io(#[from] std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, DiscordifyError>;
@@ -0,0 +1,64 @@
+// `--progress json`: one JSON object per line on stdout, for the bot and the desktop wrapper.
+// Every object has an `event` field, one of start, progress, done or error.
+
+use std::path::Path;
+use std::time::Duration;
+use serde_json::{json, Value};
+use discordify::{ByteSize, Progress, TranscodeResult};
+
+fn emit(event: Value) {
+    println!("{event}");
+}
+
+// Two decimals are plenty for seconds and percentages and keep the lines short.
+fn round(value: f64) -> f64 {
+    (value * 100.0).round() / 100.0
+}
+
+fn secs(duration: Duration) -> f64 {
+    round(duration.as_secs_f64())
+}
+
+pub fn start(input: &Path, output: &Path, target_size: ByteSize) {
+    emit(json!({
+        "event": "start",
+        "input": input.display().to_string(),
+        "output": output.display().to_string(),
+        "target_size": target_size.bytes(),
+    }));
+}
+
+pub fn progress(progress: &Progress) {
+    emit(json!({
+        "event": "progress",
+        "stage": progress.stage.name(),
+        "pass": progress.pass,
+        "frames": progress.frames,
+        "out_time": round(progress.out_time),
+        "duration": round(progress.duration),
+        "percent": round(progress.percent),
+        "output_size": progress.output_size.bytes(),
+        "elapsed": secs(progress.elapsed),
+        "speed": round(progress.speed),
+        "eta": progress.eta.map(secs),
+    }));
+}
+
+pub fn done(input: &Path, result: &TranscodeResult) {
+    emit(json!({
+        "event": "done",
+        "input": input.display().to_string(),
+        "output": result.output_path.display().to_string(),
+        "size": result.size.bytes(),
+        "passes": result.passes,
+        "from_cache": result.from_cache,
+    }));
+}
+
+pub fn error(input: &Path, message: &str) {
+    emit(json!({
+        "event": "error",
+        "input": input.display().to_string(),
+        "message": message,
+    }));
+}
@@ -0,0 +1,65 @@
+use std::cell::{Cell, RefCell};
+use std::ffi::{c_char, c_int, c_void, CStr};
+use ffmpeg_next::ffi;
+use ffmpeg_next::log;
+use tracing::level_filters::LevelFilter;
+
+// How bindgen spells `va_list` in a parameter: the x86-64 System V one is an array, which
+// decays to a pointer, everywhere else it's passed as is.
+#[cfg(all(target_arch = "x86_64", not(windows)))]
+type VaList = *mut ffi::__va_list_tag;
+#[cfg(not(all(target_arch = "x86_64", not(windows))))]
+type VaList = ffi::va_list;
+
+// ffmpeg logs lines in pieces, the prefix flag and the unfinished line carry over between calls.
+// Per thread, since the audio and video encoders log at the same time.
+thread_local! {
+    static PRINT_PREFIX: Cell<c_int> = const { Cell::new(1) };
+    static PENDING: RefCell<String> = const { RefCell::new(String::new()) };
+}
+
+/// Sends ffmpeg's log through `tracing` under the `ffmpeg` target, and has ffmpeg skip whatever
+/// the current subscriber would drop anyway. ffmpeg's info is our debug, its verbose and debug
+/// our trace.
+pub(crate) fn forward() {
+    let level = match LevelFilter::current() {
+        LevelFilter::OFF => log::Level::Quiet,
+        LevelFilter::ERROR => log::Level::Error,
+        LevelFilter::WARN | LevelFilter::INFO => log::Level::Warning,
+        LevelFilter::DEBUG => log::Level::Info,
+        LevelFilter::TRACE => log::Level::Debug,
+    };
+    log::set_level(level);
+    unsafe { ffi::av_log_set_callback(Some(callback)) };
+}
+
+unsafe extern "C" fn callback(avcl: *mut c_void, level: c_int, fmt: *const c_char, args: VaList) {
+    // The upper bits can carry a color.
+    let level = level & 0xff;
+    if level > unsafe { ffi::av_log_get_level() } {
+        return;
+    }
+    let mut line = [0 as c_char; 1024];
+    let mut print_prefix = PRINT_PREFIX.get();
+    unsafe {
+        ffi::av_log_format_line2(avcl, level, fmt, args, line.as_mut_ptr(), line.len() as c_int, &mut print_prefix);
+    }
+    PRINT_PREFIX.set(print_prefix);
+
+    let text = unsafe { CStr::from_ptr(line.as_ptr()) }.to_string_lossy();
+    PENDING.with_borrow_mut(|pending| {
+        pending.push_str(&text);
+        let Some(end) = pending.rfind('\n') else {
+            return;
+        };
+        for message in pending[..end].lines().filter(|message| !message.trim().is_empty()) {
+            match level {
+                ..=ffi::AV_LOG_ERROR => tracing::error!(target: "ffmpeg", "{message}"),
+                ..=ffi::AV_LOG_WARNING => tracing::warn!(target: "ffmpeg", "{message}"),
+                ..=ffi::AV_LOG_INFO => tracing::debug!(target: "ffmpeg", "{message}"),
+                _ => tracing::trace!(target: "ffmpeg", "{message}"),
+            }
+        }
+        pending.drain(..=end);
+    });
+}
@@ -0,0 +1,36 @@
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+use sha1::{Digest, Sha1};
+
+use crate::cancel::CancelToken;
+use crate::error::Result;
+
+// Big enough that multi-GB recordings are read in a few thousand calls rather than millions.
+const BUFFER_SIZE: usize = 1024 * 1024;
+
+/// SHA-1 of the file's contents as lowercase hex. Checks `cancel` between reads, hashing a long
+/// recording takes a while.
+pub(crate) fn sha1_file(path: &Path, cancel: &CancelToken) -> Result<String> {
+    let mut hasher = Sha1::new();
+    let mut file = File::open(path)?;
+    let mut buffer = vec![0; BUFFER_SIZE];
+    loop {
+        cancel.check()?;
+        let bytes_read = file.read(&mut buffer)?;
+        if bytes_read == 0 {
+            break;
+        }
+        hasher.update(&buffer[..bytes_read]);
+    }
+    Ok(hex(&hasher.finalize()))
+}
+
+/// SHA-1 of `bytes` as lowercase hex.
+pub(crate) fn sha1_bytes(bytes: &[u8]) -> String {
+    hex(&Sha1::digest(bytes))
+}
+
+fn hex(digest: &[u8]) -> String {
+    digest.iter().map(|byte| format!("{:02x}", byte)).collect()
+}
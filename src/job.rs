@@ -0,0 +1,687 @@
+use std::fs;
+use std::fs::metadata;
+use std::io;
+use std::panic;
+use std::path::{Path, PathBuf};
+use ffmpeg_next as ffmpeg;
+use ffmpeg_next::{format, media};
+use tokio::runtime::Handle;
+use tracing::{info, warn};
+
+use crate::{audio_codec, audio_transcode, budget, hash, video_codec, video_transcode};
+use crate::audio_transcode::PendingAudio;
+use crate::audio_codec::AudioCodec;
+use crate::cache::Cache;
+use crate::cancel::CancelToken;
+use crate::preset::Presets;
+use crate::profile::Profile;
+use crate::progress::{ProgressHandler, Reporter};
+use crate::error::{DiscordifyError, Result};
+use crate::naming::{Clobber, NameTemplate};
+use crate::size::ByteSize;
+use crate::size_control::{SizeController, Verdict};
+use crate::temp::JobDir;
+use crate::trim::{Trim, TimeRange};
+use crate::video_codec::VideoCodec;
+use crate::video_transcode::VideoOptions;
+
+#[derive(Debug, Clone)]
+pub struct TranscodeOptions {
+    /// Video codec to encode with. When `None` the profile decides.
+    pub video_codec: Option<VideoCodec>,
+    /// Audio codec to encode with. When `None` the output container decides, AAC for mp4.
+    pub audio_codec: Option<AudioCodec>,
+    pub profile: Profile,
+    /// Final output path. Overrides `output_dir` and `naming`.
+    pub output: Option<PathBuf>,
+    /// Folder to write the output to. Defaults to the input's folder.
+    pub output_dir: Option<PathBuf>,
+    /// Output file name, `discord_ready_<stem>.mp4` by default.
+    pub naming: NameTemplate,
+    /// What to do when the output file already exists.
+    pub clobber: Clobber,
+    /// Give up after this many video passes if the output is still too big.
+    pub max_passes: u32,
+    /// Cap the output height instead of picking it from the bitrate.
+    pub max_height: Option<u32>,
+    /// Cap the output frame rate instead of picking it from the bitrate.
+    pub max_fps: Option<f64>,
+    /// Only transcode this part of the input.
+    pub trim: Trim,
+    /// Upload limit table, its smallest and largest entries anchor the audio bitrate scale.
+    pub presets: Presets,
+    /// Where each job gets its folder for intermediate files. Defaults to the system temp folder.
+    pub temp_dir: Option<PathBuf>,
+    /// Leave the intermediate files behind instead of removing them when the job ends.
+    pub keep_temp: bool,
+    /// Fraction of the target size to stay under, 0.05 for 5%.
+    pub margin: f64,
+    /// Read the whole input once to put its SHA-1 in the result, even without a cache.
+    pub hash_input: bool,
+    /// Where finished outputs are kept to be handed out again for the same input and settings.
+    /// The input is hashed to look it up.
+    pub cache: Option<Cache>,
+    /// Gets the progress updates instead of them being printed to stderr.
+    pub progress: Option<ProgressHandler>,
+    /// Aborts the job once cancelled.
+    pub cancel: CancelToken,
+}
+
+impl Default for TranscodeOptions {
+    fn default() -> Self {
+        Self {
+            video_codec: None,
+            audio_codec: None,
+            profile: Profile::default(),
+            output: None,
+            output_dir: None,
+            naming: NameTemplate::default(),
+            clobber: Clobber::default(),
+            max_passes: 5,
+            max_height: None,
+            max_fps: None,
+            trim: Trim::default(),
+            presets: Presets::default(),
+            margin: 0.0,
+            temp_dir: None,
+            keep_temp: false,
+            hash_input: false,
+            cache: None,
+            progress: None,
+            cancel: CancelToken::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TranscodeJob {
+    input: PathBuf,
+    target_size: ByteSize,
+    options: TranscodeOptions,
+}
+
+#[derive(Debug, Clone)]
+pub struct TranscodeJobBuilder {
+    input: PathBuf,
+    target_size: ByteSize,
+    options: TranscodeOptions,
+}
+
+#[derive(Debug, Clone)]
+pub struct TranscodeResult {
+    pub output_path: PathBuf,
+    pub size: ByteSize,
+    /// Number of video passes it took to make the output, those of the original run for a cached
+    /// one. 0 when nothing was transcoded: the input was already small enough or the existing
+    /// output was kept with [`Clobber::Skip`].
+    pub passes: u32,
+    pub video_bit_rate: usize,
+    pub audio_bit_rate: usize,
+    /// SHA-1 of the input as lowercase hex, when it was hashed: with `hash_input` or a cache, and
+    /// only once it's clear the input needs transcoding.
+    pub input_digest: Option<String>,
+    /// The output was copied from the cache rather than transcoded.
+    pub from_cache: bool,
+}
+
+// Past this a split is almost certainly a typo in --split-every or a hopeless target size.
+const MAX_PARTS: u64 = 100;
+
+/// How to cut a long input into parts that each fit the target size.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Split {
+    /// As few equal parts as needed to keep every part watchable.
+    Auto,
+    /// A new part every this many seconds.
+    Every(f64),
+}
+
+#[derive(Debug, Clone)]
+pub struct TranscodePlan {
+    pub input_size: ByteSize,
+    /// What the output aims for, the target size with the margin taken off.
+    pub target_size: ByteSize,
+    /// Length of the output, after trimming.
+    pub duration_secs: f64,
+    pub video_bit_rate: usize,
+    pub audio_bit_rate: usize,
+    pub audio_codec: AudioCodec,
+    /// `None` keeps the input's codec.
+    pub video_codec: Option<VideoCodec>,
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate: f64,
+    pub output_path: PathBuf,
+    /// The input already fits, so it would be copied to `output_path` as it is.
+    pub already_fits: bool,
+}
+
+impl TranscodeJobBuilder {
+    pub fn target_size(mut self, size: ByteSize) -> Self {
+        self.target_size = size;
+        self
+    }
+
+    pub fn margin(mut self, margin: f64) -> Self {
+        self.options.margin = margin;
+        self
+    }
+
+    pub fn video_codec(mut self, codec: VideoCodec) -> Self {
+        self.options.video_codec = Some(codec);
+        self
+    }
+
+    pub fn audio_codec(mut self, codec: AudioCodec) -> Self {
+        self.options.audio_codec = Some(codec);
+        self
+    }
+
+    pub fn profile(mut self, profile: Profile) -> Self {
+        self.options.profile = profile;
+        self
+    }
+
+    pub fn output(mut self, path: impl Into<PathBuf>) -> Self {
+        self.options.output = Some(path.into());
+        self
+    }
+
+    pub fn output_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.options.output_dir = Some(dir.into());
+        self
+    }
+
+    pub fn naming(mut self, template: NameTemplate) -> Self {
+        self.options.naming = template;
+        self
+    }
+
+    pub fn clobber(mut self, clobber: Clobber) -> Self {
+        self.options.clobber = clobber;
+        self
+    }
+
+    pub fn temp_dir(mut self, dir: impl Into<PathBuf>) -> Self {
+        self.options.temp_dir = Some(dir.into());
+        self
+    }
+
+    pub fn keep_temp(mut self, keep: bool) -> Self {
+        self.options.keep_temp = keep;
+        self
+    }
+
+    pub fn hash_input(mut self, hash: bool) -> Self {
+        self.options.hash_input = hash;
+        self
+    }
+
+    pub fn cache(mut self, cache: Cache) -> Self {
+        self.options.cache = Some(cache);
+        self
+    }
+
+    pub fn progress(mut self, handler: ProgressHandler) -> Self {
+        self.options.progress = Some(handler);
+        self
+    }
+
+    pub fn cancel(mut self, cancel: CancelToken) -> Self {
+        self.options.cancel = cancel;
+        self
+    }
+
+    pub fn max_passes(mut self, max_passes: u32) -> Self {
+        self.options.max_passes = max_passes;
+        self
+    }
+
+    pub fn max_height(mut self, max_height: u32) -> Self {
+        self.options.max_height = Some(max_height);
+        self
+    }
+
+    pub fn max_fps(mut self, max_fps: f64) -> Self {
+        self.options.max_fps = Some(max_fps);
+        self
+    }
+
+    pub fn start(mut self, secs: f64) -> Self {
+        self.options.trim.start = Some(secs);
+        self
+    }
+
+    pub fn end(mut self, secs: f64) -> Self {
+        self.options.trim.end = Some(secs);
+        self
+    }
+
+    pub fn duration(mut self, secs: f64) -> Self {
+        self.options.trim.duration = Some(secs);
+        self
+    }
+
+    pub fn presets(mut self, presets: Presets) -> Self {
+        self.options.presets = presets;
+        self
+    }
+
+    pub fn options(mut self, options: TranscodeOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn build(self) -> TranscodeJob {
+        TranscodeJob {
+            input: self.input,
+            target_size: self.target_size,
+            options: self.options,
+        }
+    }
+}
+
+impl TranscodeJob {
+    pub fn builder(input: impl Into<PathBuf>) -> TranscodeJobBuilder {
+        TranscodeJobBuilder {
+            input: input.into(),
+            target_size: ByteSize::from_mb(10.0),
+            options: TranscodeOptions::default(),
+        }
+    }
+
+    pub fn input(&self) -> &Path {
+        &self.input
+    }
+
+    pub fn target_size(&self) -> ByteSize {
+        self.target_size
+    }
+
+    pub fn options(&self) -> &TranscodeOptions {
+        &self.options
+    }
+
+    pub fn output_path(&self) -> Result<PathBuf> {
+        if let Some(path) = &self.options.output {
+            return Ok(path.clone());
+        }
+        let dir = match &self.options.output_dir {
+            Some(dir) => dir.as_path(),
+            None => self.input_dir()?,
+        };
+        Ok(dir.join(self.options.naming.render(&self.input, self.target_size)?))
+    }
+
+    fn audio_codec(&self) -> Result<AudioCodec> {
+        let output_path = self.output_path()?;
+        audio_codec::resolve(self.options.audio_codec, container_extension(&output_path))
+    }
+
+    fn time_range(&self) -> Result<Option<TimeRange>> {
+        if self.options.trim.is_empty() {
+            return Ok(None);
+        }
+        let ictx = format::input(&self.input).map_err(|source| DiscordifyError::Probe { path: self.input.clone(), source })?;
+        let input_duration = ictx.duration() as f64 / f64::from(ffmpeg::ffi::AV_TIME_BASE);
+        self.options.trim.range(input_duration)
+    }
+
+    fn video_codec(&self) -> Result<Option<VideoCodec>> {
+        let output_path = self.output_path()?;
+        video_codec::resolve(self.options.video_codec, self.options.profile, container_extension(&output_path))
+    }
+
+    fn video_options(&self, trim: Option<TimeRange>) -> Result<VideoOptions> {
+        Ok(VideoOptions {
+            video_codec: self.video_codec()?,
+            profile: self.options.profile,
+            max_height: self.options.max_height,
+            max_fps: self.options.max_fps,
+            trim,
+        })
+    }
+
+    // The size every pass has to get under.
+    fn target(&self) -> ByteSize {
+        self.target_size.with_margin(self.options.margin)
+    }
+
+    // Everything that changes what comes out, so a different setting never gets an old output.
+    // How many passes it may take, where the output goes and the temp folder don't count.
+    fn cache_key(&self, input_digest: &str) -> Result<String> {
+        let options = &self.options;
+        let target = self.target();
+        let settings = format!(
+            "{} {} {} {:?} {:?} {} {:?} {:?} {:?} {} {}",
+            env!("CARGO_PKG_VERSION"),
+            input_digest,
+            target.bytes(),
+            options.video_codec,
+            options.audio_codec,
+            options.profile,
+            options.max_height,
+            options.max_fps,
+            options.trim,
+            audio_transcode::audio_bit_rate(target, &options.presets),
+            container_extension(&self.output_path()?),
+        );
+        Ok(hash::sha1_bytes(settings.as_bytes()))
+    }
+
+    fn input_dir(&self) -> Result<&Path> {
+        self.input
+            .parent()
+            .ok_or_else(|| DiscordifyError::IncompletePath { path: self.input.clone(), part: "parent folder" })
+    }
+
+    pub fn plan(&self) -> Result<TranscodePlan> {
+        crate::init()?;
+
+        let target = self.target();
+        let input_size = ByteSize::from_bytes(metadata(&self.input)?.len());
+        let ictx = format::input(&self.input).map_err(|source| DiscordifyError::Probe { path: self.input.clone(), source })?;
+        let video_probe = video_transcode::probe(&ictx)?;
+        let input_duration = ictx.duration() as f64 / f64::from(ffmpeg::ffi::AV_TIME_BASE);
+        let trim = self.options.trim.range(input_duration)?;
+        let duration_secs = trim.map_or(input_duration, |range| range.duration());
+        let has_audio = ictx.streams().best(media::Type::Audio).is_some();
+        let audio_bit_rate = if has_audio { audio_transcode::audio_bit_rate(target, &self.options.presets) } else { 0 };
+        let audio_size = audio_transcode::expected_size(audio_bit_rate, duration_secs);
+        let video_bit_rate = target.saturating_sub(audio_size).bytes() as f64 * 8.0 / duration_secs;
+        let audio_codec = self.audio_codec()?;
+        // A trimmed slice always gets transcoded, even when the whole input would fit.
+        let already_fits = trim.is_none() && input_size <= target;
+        if !already_fits && (duration_secs.is_nan() || duration_secs <= 0.0) {
+            return Err(DiscordifyError::UnknownDuration(self.input.clone()));
+        }
+        if !already_fits && video_bit_rate <= 0.0 {
+            return Err(DiscordifyError::SizeTargetUnreachable { wanted: target, audio: audio_size });
+        }
+
+        let video_options = self.video_options(trim)?;
+        let shape = video_options.output_shape(&video_probe, video_bit_rate.max(0.0) as usize);
+
+        Ok(TranscodePlan {
+            input_size,
+            target_size: target,
+            duration_secs,
+            video_bit_rate: video_bit_rate as usize,
+            audio_bit_rate,
+            audio_codec,
+            video_codec: video_options.video_codec,
+            width: shape.width,
+            height: shape.height,
+            frame_rate: shape.frame_rate,
+            output_path: self.output_path()?,
+            already_fits,
+        })
+    }
+
+    /// Cuts the job into one job per part, each trimmed to its slice of the input and writing
+    /// `<output stem>_part<n>.<ext>`. Returns just this job when one part is enough.
+    pub fn split(&self, split: Split) -> Result<Vec<TranscodeJob>> {
+        crate::init()?;
+
+        let ictx = format::input(&self.input).map_err(|source| DiscordifyError::Probe { path: self.input.clone(), source })?;
+        let input_duration = ictx.duration() as f64 / f64::from(ffmpeg::ffi::AV_TIME_BASE);
+        let (start, end) = match self.options.trim.range(input_duration)? {
+            Some(range) => (range.start, range.end),
+            None => (0.0, input_duration),
+        };
+        let duration = end - start;
+
+        let (parts, part_secs) = match split {
+            Split::Every(secs) if secs > 0.0 && secs.is_finite() => ((duration / secs).ceil(), secs),
+            Split::Every(secs) => return Err(DiscordifyError::InvalidSplit(format!("can't split every {secs}s"))),
+            Split::Auto => {
+                let video_probe = video_transcode::probe(&ictx)?;
+                let has_audio = ictx.streams().best(media::Type::Audio).is_some();
+                let audio_bit_rate = if has_audio { audio_transcode::audio_bit_rate(self.target(), &self.options.presets) } else { 0 };
+                let min_video_bit_rate = budget::min_acceptable_bit_rate(
+                    video_probe.width,
+                    video_probe.height,
+                    video_probe.frame_rate,
+                    budget::min_bits_per_pixel(self.video_options(None)?.resolved_codec()),
+                );
+                // Leave some room for the container and the encoder missing its bitrate.
+                let target_bits = self.target().bytes() as f64 * 8.0 * 0.95;
+                let max_secs = target_bits / (min_video_bit_rate + audio_bit_rate as f64);
+                // Equal parts rather than full parts and a short leftover.
+                let parts = (duration / max_secs).ceil();
+                (parts, duration / parts)
+            }
+        };
+        if parts.is_nan() || parts <= 1.0 {
+            return Ok(vec![self.clone()]);
+        }
+        if parts > MAX_PARTS as f64 {
+            return Err(DiscordifyError::TooManyParts { parts: parts.min(u64::MAX as f64) as u64, max: MAX_PARTS });
+        }
+        let parts = parts as usize;
+
+        let output_path = self.output_path()?;
+        let stem = output_path
+            .file_stem()
+            .ok_or_else(|| DiscordifyError::IncompletePath { path: output_path.clone(), part: "file name" })?
+            .to_str()
+            .ok_or_else(|| DiscordifyError::InvalidPath(output_path.clone()))?;
+        let extension = container_extension(&output_path);
+
+        let jobs = (0..parts)
+            .map(|index| {
+                let part_start = start + index as f64 * part_secs;
+                let part_end = if index + 1 == parts { end } else { part_start + part_secs };
+                let mut options = self.options.clone();
+                options.output = Some(output_path.with_file_name(format!("{stem}_part{}.{extension}", index + 1)));
+                options.trim = Trim { start: Some(part_start), end: Some(part_end), duration: None };
+                TranscodeJob {
+                    input: self.input.clone(),
+                    target_size: self.target_size,
+                    options,
+                }
+            })
+            .collect();
+        Ok(jobs)
+    }
+
+    /// Runs the job on Tokio's blocking pool, so the encodes don't hold up the runtime's other
+    /// tasks. Outside a runtime this is [`TranscodeJob::run_blocking`].
+    pub async fn run(&self) -> Result<TranscodeResult> {
+        let Ok(runtime) = Handle::try_current() else {
+            return self.run_blocking();
+        };
+        let job = self.clone();
+        match runtime.spawn_blocking(move || job.run_blocking()).await {
+            Ok(result) => result,
+            Err(e) if e.is_panic() => panic::resume_unwind(e.into_panic()),
+            Err(e) => Err(io::Error::other(e).into()),
+        }
+    }
+
+    /// Runs the job on the calling thread until it's done, with the audio encoded on a thread
+    /// of its own alongside the video.
+    pub fn run_blocking(&self) -> Result<TranscodeResult> {
+        crate::init()?;
+        let mut reporter = Reporter::new(self.options.progress.clone());
+        let input_file = &self.input;
+        let input_size = self.target();
+
+        let video_size = ByteSize::from_bytes(metadata(&input_file)?.len());
+        let trim = self.time_range()?;
+        let cancel = &self.options.cancel;
+        let final_output_path = self.output_path()?;
+        if final_output_path.exists() {
+            match self.options.clobber {
+                Clobber::Refuse => return Err(DiscordifyError::OutputExists(final_output_path)),
+                Clobber::Skip => {
+                    info!("{} already exists, skipping", final_output_path.display());
+                    return Ok(TranscodeResult {
+                        size: ByteSize::from_bytes(metadata(&final_output_path)?.len()),
+                        output_path: final_output_path,
+                        passes: 0,
+                        video_bit_rate: 0,
+                        audio_bit_rate: 0,
+                        input_digest: None,
+                        from_cache: false,
+                    });
+                }
+                Clobber::Overwrite => {}
+            }
+        }
+
+        if trim.is_none() && video_size <= input_size {
+            info!("File is {video_size} which is already below {input_size}, copying it as is");
+            // Named so the output is the input itself, there's nothing to copy.
+            if !same_file(input_file, &final_output_path) {
+                create_parent_dir(&final_output_path)?;
+                fs::copy(input_file, &final_output_path)?;
+            }
+            return Ok(TranscodeResult {
+                output_path: final_output_path,
+                size: video_size,
+                passes: 0,
+                video_bit_rate: 0,
+                audio_bit_rate: 0,
+                input_digest: None,
+                from_cache: false,
+            });
+        }
+
+        // Reading a multi-GB recording takes a while, only done when something uses the digest.
+        let input_digest = match self.options.hash_input || self.options.cache.is_some() {
+            true => Some(hash::sha1_file(input_file, cancel)?),
+            false => None,
+        };
+
+        let cached = match (&self.options.cache, &input_digest) {
+            (Some(cache), Some(digest)) => Some((cache, self.cache_key(digest)?)),
+            _ => None,
+        };
+        if let Some(entry) = cached.as_ref().and_then(|(cache, key)| cache.get(key)) {
+            info!("Same input and settings as {}, copying the cached result", entry.input.display());
+            create_parent_dir(&final_output_path)?;
+            fs::copy(&entry.path, &final_output_path)?;
+            return Ok(TranscodeResult {
+                output_path: final_output_path,
+                size: entry.size,
+                passes: entry.passes,
+                video_bit_rate: entry.video_bit_rate,
+                audio_bit_rate: entry.audio_bit_rate,
+                input_digest,
+                from_cache: true,
+            });
+        }
+        let audio_codec = self.audio_codec()?;
+        let video_options = self.video_options(trim)?;
+        let ictx = format::input(input_file).map_err(|source| DiscordifyError::Probe { path: input_file.clone(), source })?;
+        let has_audio = ictx.streams().best(media::Type::Audio).is_some();
+        let duration_secs = trim.map_or(ictx.duration() as f64 / f64::from(ffmpeg::ffi::AV_TIME_BASE), |range| range.duration());
+        drop(ictx);
+        let audio_bit_rate = if has_audio { audio_transcode::audio_bit_rate(input_size, &self.options.presets) } else { 0 };
+        let audio_size = audio_transcode::expected_size(audio_bit_rate, duration_secs);
+
+        // Removed with everything in it when this function returns, however it returns.
+        let job_dir = JobDir::create(self.options.temp_dir.as_deref(), self.options.keep_temp)?;
+        // Declared after the folder so it's dropped first, which waits for the encode to stop.
+        let mut audio = {
+            let (input_file, dir, reporter) = (input_file.clone(), job_dir.path().to_path_buf(), reporter.clone());
+            PendingAudio::start(audio_size, cancel, move |cancel| {
+                audio_transcode::audio(&input_file, &dir, audio_bit_rate, audio_codec, trim, &reporter, cancel)
+            })
+        };
+
+        let output_path = job_dir.path()
+            .join("video")
+            .with_extension(container_extension(&final_output_path));
+
+        let mut controller = SizeController::new(input_size, audio_size, self.options.max_passes);
+        let mut target_size = controller.first_request();
+
+        let video_result = loop {
+            reporter.pass = controller.passes() + 1;
+            let (video_output_path, video_bit_rate) = match video_transcode::video(input_file.clone(), &mut audio, output_path.clone(), target_size, &video_options, &reporter, cancel) {
+                Ok(output) => output,
+                Err(e) => break Err(e),
+            };
+
+            let video_size = match metadata(&video_output_path) {
+                Ok(meta) => ByteSize::from_bytes(meta.len()),
+                Err(e) => break Err(e.into()),
+            };
+            // The audio is done by the end of the first pass, its actual size replaces the estimate.
+            controller.set_audio_size(audio.size());
+            match controller.record(target_size, video_size) {
+                Verdict::Done => {
+                    info!("Video transcoding complete: {}", video_size);
+                    break Ok((video_output_path, video_bit_rate, video_size));
+                }
+                Verdict::Retry(next_size) => {
+                    info!("Video pass failed: wanted {}, received {}. Starting next pass aiming for {}", input_size, video_size, next_size);
+                    target_size = next_size;
+                }
+                Verdict::GiveUp => {
+                    break Err(DiscordifyError::SizeTargetNotReached {
+                        wanted: input_size,
+                        best: controller.best().unwrap_or(video_size),
+                        passes: controller.passes(),
+                    });
+                }
+            }
+        };
+        let passes = controller.passes();
+        let (video_output_path, video_bit_rate, video_size) = video_result?;
+
+        create_parent_dir(&final_output_path)?;
+        // A kept folder keeps its copy too, so it has every pass's output in it.
+        if job_dir.is_kept() {
+            fs::copy(&video_output_path, &final_output_path)?;
+        } else {
+            move_file(&video_output_path, &final_output_path)?;
+        }
+
+        let result = TranscodeResult {
+            output_path: final_output_path,
+            size: video_size,
+            passes,
+            video_bit_rate,
+            audio_bit_rate,
+            input_digest,
+            from_cache: false,
+        };
+        if let Some((cache, key)) = &cached
+            && let Err(e) = cache.insert(key, input_file, self.target_size, &result)
+        {
+            warn!("Couldn't add the result to the cache: {}", e);
+        }
+        Ok(result)
+    }
+}
+
+fn same_file(a: &Path, b: &Path) -> bool {
+    match (fs::canonicalize(a), fs::canonicalize(b)) {
+        (Ok(a), Ok(b)) => a == b,
+        _ => false,
+    }
+}
+
+fn create_parent_dir(path: &Path) -> Result<()> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    Ok(())
+}
+
+// The temp folder is often on another filesystem than the output, where a rename won't do.
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+// The intermediate video takes the final extension so it gets muxed into the right container.
+fn container_extension(path: &Path) -> &str {
+    path.extension().and_then(|extension| extension.to_str()).unwrap_or("mp4")
+}
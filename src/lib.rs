@@ -0,0 +1,43 @@
+mod audio_transcode;
+mod video_transcode;
+mod size_control;
+mod budget;
+mod hash;
+mod temp;
+mod ffmpeg_log;
+pub mod audio_codec;
+pub mod cache;
+pub mod cancel;
+pub mod error;
+pub mod job;
+pub mod naming;
+pub mod preset;
+pub mod profile;
+pub mod progress;
+pub mod size;
+pub mod trim;
+pub mod video_codec;
+
+pub use audio_codec::AudioCodec;
+pub use cache::{Cache, CacheEntry};
+pub use cancel::CancelToken;
+pub use error::DiscordifyError;
+pub use naming::{Clobber, NameTemplate};
+pub use preset::{Preset, Presets};
+pub use profile::Profile;
+pub use progress::{Progress, ProgressHandler, Stage};
+pub use size::ByteSize;
+pub use temp::remove_temp_dirs;
+pub use trim::Trim;
+pub use video_codec::VideoCodec;
+pub use job::{Split, TranscodeJob, TranscodeJobBuilder, TranscodeOptions, TranscodePlan, TranscodeResult};
+
+use ffmpeg_next as ffmpeg;
+
+/// Sets up ffmpeg and forwards its log to `tracing`, so install the subscriber first: ffmpeg only
+/// produces the levels it enables.
+pub fn init() -> Result<(), ffmpeg::Error> {
+    ffmpeg::init()?;
+    ffmpeg_log::forward();
+    Ok(())
+}
@@ -0,0 +1,37 @@
+use std::fs::OpenOptions;
+use std::io;
+use std::sync::Mutex;
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::fmt;
+
+use crate::cli::LogArgs;
+
+/// Logs to stderr at the level picked with -q/-v/-vv, and to --log-file when given, with
+/// timestamps and at least debug detail so a bug report has something to go on.
+pub fn init(args: &LogArgs) -> io::Result<()> {
+    let level = match (args.quiet, args.verbose) {
+        (true, _) => LevelFilter::ERROR,
+        (false, 0) => LevelFilter::INFO,
+        (false, 1) => LevelFilter::DEBUG,
+        (false, _) => LevelFilter::TRACE,
+    };
+    let stderr = fmt::layer()
+        .with_writer(io::stderr)
+        .without_time()
+        .with_target(false)
+        .with_filter(level);
+    let file = match &args.log_file {
+        Some(path) => {
+            let file = OpenOptions::new().create(true).append(true).open(path)?;
+            let layer = fmt::layer()
+                .with_writer(Mutex::new(file))
+                .with_ansi(false)
+                .with_filter(level.max(LevelFilter::DEBUG));
+            Some(layer)
+        }
+        None => None,
+    };
+    tracing_subscriber::registry().with(stderr).with(file).init();
+    Ok(())
+}
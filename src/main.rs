@@ -1,19 +1,18 @@
-mod video_transcode;
-mod audio_transcode;
 mod utils;
-
-use std::{env, fs};
-use std::fs::{metadata, File};
-use std::io::Read;
-use std::path::{Path};
+mod cli;
+mod batch;
+mod watch;
+mod cache_command;
+mod events;
+mod logging;
+
+use std::fs::metadata;
+use std::process::ExitCode;
 use std::time::Instant;
 use tokio;
-use sha1::{Sha1, Digest};
-use ffmpeg_next as ffmpeg;
-use ffmpeg_next::log;
 use rust_embed::Embed;
-
-const OVERRIDDEN_PATH:&str = "";
+use tracing::{debug, error, info, warn};
+use discordify::{ByteSize, CancelToken, DiscordifyError, Split, TranscodeJob, TranscodeOptions, Trim};
 
 #[derive(Embed)]
 #[folder = "assets/"]
@@ -21,131 +20,222 @@ const OVERRIDDEN_PATH:&str = "";
 struct Asset;
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let input_file: String;
-    let input_size: f32;
-    if OVERRIDDEN_PATH.is_empty() {
-        input_file = env::args().nth(1).expect("missing input file");
-    } else {
-        input_file = OVERRIDDEN_PATH.to_string();
+async fn main() -> ExitCode {
+    let args = cli::Args::parse_and_validate();
+    if let Err(e) = logging::init(&args.log) {
+        eprintln!("Can't open the log file: {}", e);
+        return ExitCode::FAILURE;
     }
 
-    input_size = env::args().nth(2).expect("missing set file-size").parse().expect("unable to parse input size");
-
-    let input_file = Path::new(&input_file).to_path_buf();
-
-    match metadata(&input_file) {
-        Ok(meta) => {
-            let file_size_bytes = meta.len();
-            let video_size = file_size_bytes as f32 / (1024.0 * 1024.0);
-            if video_size <= input_size {
-                println!("[RUST] File is {video_size} MB which is already below {input_size} MB, so nothing happened!");
-                utils::copy_video_file(&input_file);
-                return Ok(());
-            }
-        }
-        Err(e) => {
-            eprintln!("Error reading file metadata: {}", e);
+    // The first Ctrl-C lets the running job stop and clean up after itself. A second one exits
+    // on the spot, which skips the destructors that would remove its temp folder.
+    let cancel = CancelToken::new();
+    let handler_cancel = cancel.clone();
+    if let Err(e) = ctrlc::set_handler(move || {
+        if handler_cancel.is_cancelled() {
+            discordify::remove_temp_dirs();
+            std::process::exit(130);
         }
+        warn!("Stopping, press Ctrl-C again to quit right away");
+        handler_cancel.cancel();
+    }) {
+        warn!("Can't catch Ctrl-C, an interrupted job will leave its temp files behind: {}", e);
     }
-    let actual_start_time;
-    match ffmpeg::init() {
-        Ok(_) => {
-            println!("FFmpeg initialized successfully.");
-            log::set_level(log::Level::Error);
-            actual_start_time = Instant::now();
-        },
+
+    match run(&args, &cancel).await {
+        Ok(0) => ExitCode::SUCCESS,
+        Ok(_) => ExitCode::FAILURE,
+        Err(DiscordifyError::Cancelled) => ExitCode::from(130),
         Err(e) => {
-            eprintln!("Failed to initialize FFmpeg: {}", e);
-            return Err(e.into());
+            error!("{}", e);
+            ExitCode::FAILURE
         }
     }
+}
 
-    let audio_output_path = audio_transcode::audio(&input_file, &input_size, actual_start_time).await.unwrap_or_else(| _e |None);
-    let mut hasher = Sha1::new();
-    let mut file = File::open(&input_file).unwrap();
+// Keeps going when a file fails and returns how many did.
+async fn run(args: &cli::Args, cancel: &CancelToken) -> Result<usize, DiscordifyError> {
+    if let Err(e) = discordify::init() {
+        error!("Failed to initialize FFmpeg: {}", e);
+        return Err(e.into());
+    }
+    debug!("FFmpeg initialized");
 
-    let mut buffer = [0; 1024];
-    loop {
-        let bytes_read = file.read(&mut buffer).unwrap();
-        if bytes_read == 0 {
-            break;
+    match &args.command {
+        Some(cli::Command::Watch(watch_args)) => {
+            watch::run(watch_args, cancel).await?;
+            return Ok(0);
         }
-        hasher.update(&buffer[..bytes_read]);
+        Some(cli::Command::Cache(cache_args)) => {
+            cache_command::run(cache_args)?;
+            return Ok(0);
+        }
+        None => {}
+    }
+
+    let presets = args.transcode.presets()?;
+    let target_size = args.transcode.target_size(&presets)?;
+
+    let filter = batch::Filter {
+        extensions: args.extensions.clone(),
+        glob: args.glob.clone(),
+        recursive: args.recursive,
+        naming: args.transcode.name.clone(),
+    };
+    let inputs = batch::collect_inputs(&args.inputs, &filter);
+    if inputs.is_empty() {
+        error!("No video files found");
+        return Ok(1);
     }
-    let result = hasher.finalize();
-    let input_file_name = result.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
-
-    let output_path = input_file
-        .parent()
-        .expect("input file must have a parent directory")
-        .join(input_file_name)
-        .with_extension("mp4");
-
-    let final_output_path = input_file
-        .parent()
-        .expect("input file must have a parent directory")
-        .join("discord_ready_video")
-        .with_extension("mp4");
-
-    let mut video_size:f32;
-    let mut video_output_path;
-    let mut additional_shrink_mb = 0.0;
-    let mut shrink_ratio;
-    
-    loop {
-        let target_size = input_size - additional_shrink_mb;
-        video_output_path = video_transcode::video(input_file.clone(), &audio_output_path, output_path.clone(), &target_size, actual_start_time).await;
-
-        match metadata(&video_output_path) {
-            Ok(meta) => {
-                let file_size_bytes = meta.len();
-                video_size = file_size_bytes as f32 / (1024.0 * 1024.0);
-                if video_size <= input_size {
-                    println!("[RUST] Video transcoding complete: {} MB", video_size);
-                    break;
-                } else {
-                    println!("[RUST] Video pass failed: wanted {:.2} MB, received {:.2} MB. Starting next pass...", input_size, video_size);
-                    shrink_ratio = video_size / 25.0;
-                    additional_shrink_mb += shrink_ratio;
+
+    let mut outcomes = Vec::new();
+    let mut output_paths = Vec::new();
+    for input_file in &inputs {
+        let job = TranscodeJob::builder(input_file)
+            .target_size(target_size)
+            .options(TranscodeOptions {
+                output: args.output.clone(),
+                output_dir: args.output_dir.clone(),
+                cancel: cancel.clone(),
+                trim: Trim {
+                    start: args.start,
+                    end: args.end,
+                    duration: args.duration,
+                },
+                ..args.transcode.options(presets.clone())
+            })
+            .build();
+
+        let split = match (args.split, args.split_every) {
+            (_, Some(secs)) => Some(Split::Every(secs)),
+            (true, None) => Some(Split::Auto),
+            (false, None) => None,
+        };
+        let input_size = metadata(input_file).map(|meta| ByteSize::from_bytes(meta.len())).unwrap_or_default();
+        let jobs = match split {
+            Some(split) => match job.split(split) {
+                Ok(jobs) => jobs,
+                Err(e) => {
+                    if args.transcode.progress == cli::ProgressFormat::Json && !args.dry_run {
+                        events::error(input_file, &e.to_string());
+                    }
+                    error!("{}: {}", input_file.display(), e);
+                    outcomes.push(batch::Outcome {
+                        name: input_file.display().to_string(),
+                        input_size,
+                        elapsed: Default::default(),
+                        result: Err(e.to_string()),
+                    });
+                    continue;
                 }
+            },
+            None => vec![job],
+        };
+        if jobs.len() > 1 {
+            info!("Splitting {} into {} parts", input_file.display(), jobs.len());
+        }
+
+        for (index, job) in jobs.iter().enumerate() {
+            let name = match jobs.len() {
+                1 => input_file.display().to_string(),
+                _ => format!("{} (part {})", input_file.display(), index + 1),
+            };
+            let start = Instant::now();
+            let json = args.transcode.progress == cli::ProgressFormat::Json && !args.dry_run;
+            if json && let Ok(output_path) = job.output_path() {
+                events::start(input_file, &output_path, target_size);
+            }
+            let result = if args.dry_run {
+                dry_run(job).map(|_| None)
+            } else {
+                job.run().await.map(Some)
+            };
+            match &result {
+                Ok(Some(result)) if json => events::done(input_file, result),
+                Err(e) if json => events::error(input_file, &e.to_string()),
+                _ => {}
             }
-            Err(e) => {
-                eprintln!("Error getting file metadata: {}", e);
-                break;
+            if let Err(e) = &result {
+                error!("{}: {}", name, e);
             }
+            // Leave the remaining files, the summary, the clipboard and the sound alone.
+            if let Err(DiscordifyError::Cancelled) = result {
+                return Err(DiscordifyError::Cancelled);
+            }
+            outcomes.push(batch::Outcome {
+                name,
+                input_size,
+                elapsed: start.elapsed(),
+                result: match result {
+                    Ok(Some(result)) => {
+                        let summary = (result.size, result.passes);
+                        output_paths.push(result.output_path);
+                        Ok(summary)
+                    }
+                    Ok(None) => Ok((ByteSize::default(), 0)),
+                    Err(e) => Err(e.to_string()),
+                },
+            });
         }
     }
 
-    let audio_output_path_str: Option<String> = match audio_output_path {
-        Some(path) => path.to_str().map(|s| s.to_string()), // Convert to String
-        None => None,
-    };
+    // With JSON progress stdout is only events, the done and error events already say it all.
+    if !args.dry_run && outcomes.len() > 1 && args.transcode.progress == cli::ProgressFormat::Text {
+        batch::print_summary(&outcomes);
+    }
+    if !args.dry_run && !args.transcode.no_clipboard && !output_paths.is_empty() {
+        utils::copy_video_files(&output_paths);
+    }
+    if !args.dry_run && !args.transcode.no_sound {
+        play_done_sound();
+    }
 
-    match &audio_output_path_str {
-        Some(path) => {
-            match fs::remove_file(&path) {
-                Ok(_) => {},
-                Err(e) => eprintln!("Error removing audio file: {}", e),
-            }
-        },
-        None => {}
+    Ok(outcomes.iter().filter(|outcome| outcome.result.is_err()).count())
+}
+
+fn dry_run(job: &TranscodeJob) -> Result<(), DiscordifyError> {
+    let plan = job.plan()?;
+
+    println!("[RUST] Input: {} ({})", job.input().display(), plan.input_size);
+    if plan.already_fits {
+        println!("[RUST] File is already below {}, it would be copied to {} as is", plan.target_size, plan.output_path.display());
+        return Ok(());
     }
 
-    match fs::rename(&video_output_path, &final_output_path) {
-        Ok(_) => {},
-        Err(e) => eprintln!("Error renaming file: {}", e),
+    println!("[RUST] Duration: {:.2}s", plan.duration_secs);
+    if plan.target_size == job.target_size() {
+        println!("[RUST] Target size: {}", plan.target_size);
+    } else {
+        println!("[RUST] Target size: {} ({} with a {}% margin)", plan.target_size, job.target_size(), job.options().margin * 100.0);
+    }
+    println!("[RUST] Audio bitrate: {} kbps ({})", plan.audio_bit_rate / 1000, plan.audio_codec);
+    println!("[RUST] Video bitrate: {} kbps", plan.video_bit_rate / 1000);
+    println!("[RUST] Resolution: {}x{} @ {:.2} fps", plan.width, plan.height, plan.frame_rate);
+    match plan.video_codec {
+        Some(codec) => println!("[RUST] Video codec: {}", codec),
+        None => println!("[RUST] Video codec: same as input"),
     }
+    println!("[RUST] Profile: {}", job.options().profile);
+    println!("[RUST] Output: {}", plan.output_path.display());
 
-    utils::copy_video_file(&final_output_path);
+    Ok(())
+}
 
-    let (_stream, stream_handle) = rodio::OutputStream::try_default().unwrap();
-    let embedded_file = Asset::get("assets/done.wav").unwrap();
+fn play_done_sound() {
+    let Ok((_stream, stream_handle)) = rodio::OutputStream::try_default() else {
+        warn!("No audio output available, skipping done sound..");
+        return;
+    };
+    let Some(embedded_file) = Asset::get("assets/done.wav") else {
+        return;
+    };
     let cursor = std::io::Cursor::new(embedded_file.data); // Use the correct field to access the data
-    let beep1 = stream_handle.play_once(cursor).unwrap();
-    beep1.set_volume(0.2);
-    beep1.sleep_until_end();
-    drop(beep1);
-
-    Ok(())
+    match stream_handle.play_once(cursor) {
+        Ok(beep1) => {
+            beep1.set_volume(0.2);
+            beep1.sleep_until_end();
+            drop(beep1);
+        }
+        Err(e) => warn!("Failed to play done sound: {}", e),
+    }
 }
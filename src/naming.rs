@@ -0,0 +1,184 @@
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::error::{DiscordifyError, Result};
+use crate::size::ByteSize;
+
+const PLACEHOLDERS: [&str; 3] = ["stem", "size", "ext"];
+
+/// File name for an output, built from the input.
+///
+/// `{stem}` is the input's file name without its extension, `{ext}` the input's extension and
+/// `{size}` the target size in MB, so `{stem}_{size}MB.{ext}` turns `clip.mkv` aimed at 10 MB
+/// into `clip_10MB.mkv`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct NameTemplate(String);
+
+impl Default for NameTemplate {
+    fn default() -> Self {
+        Self("discord_ready_{stem}.mp4".to_string())
+    }
+}
+
+impl NameTemplate {
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    pub fn render(&self, input: &Path, target_size: ByteSize) -> Result<String> {
+        let stem = input
+            .file_stem()
+            .ok_or_else(|| DiscordifyError::IncompletePath { path: input.to_path_buf(), part: "file name" })?
+            .to_str()
+            .ok_or_else(|| DiscordifyError::InvalidPath(input.to_path_buf()))?;
+        let extension = input.extension().and_then(|extension| extension.to_str()).unwrap_or("mp4");
+        // 10 rather than 10.00, but 24.5 and 26.21 stay as they are.
+        let size = format!("{:.2}", target_size.as_mb());
+        let size = size.trim_end_matches('0').trim_end_matches('.');
+
+        Ok(self
+            .0
+            .replace("{stem}", stem)
+            .replace("{size}", size)
+            .replace("{ext}", &extension.to_ascii_lowercase()))
+    }
+
+    /// Whether `file_name` looks like something this template wrote, so a folder scan can leave
+    /// earlier results alone. Placeholders match anything. A template with no text of its own
+    /// besides dots, like `{stem}.{ext}`, matches every input too and so matches nothing here.
+    pub fn is_output_name(&self, file_name: &str) -> bool {
+        let literals: Vec<&str> = self
+            .0
+            .split('{')
+            .enumerate()
+            .map(|(index, piece)| match index {
+                0 => piece,
+                _ => piece.split_once('}').map_or(piece, |(_, literal)| literal),
+            })
+            .collect();
+        if literals.iter().all(|literal| literal.chars().all(|c| c == '.')) {
+            return false;
+        }
+
+        let (first, last) = (literals[0], literals[literals.len() - 1]);
+        if literals.len() == 1 {
+            return file_name == first;
+        }
+        if file_name.len() < first.len() + last.len() || !file_name.starts_with(first) || !file_name.ends_with(last) {
+            return false;
+        }
+        let mut rest = &file_name[first.len()..file_name.len() - last.len()];
+        for literal in &literals[1..literals.len() - 1] {
+            match rest.find(literal) {
+                Some(at) => rest = &rest[at + literal.len()..],
+                None => return false,
+            }
+        }
+        true
+    }
+}
+
+impl fmt::Display for NameTemplate {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl FromStr for NameTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if s.trim().is_empty() {
+            return Err("name template is empty".to_string());
+        }
+        if s.contains(['/', '\\']) {
+            return Err(format!("`{s}` is a path, the template is only the file name (use --output-dir for the folder)"));
+        }
+        let mut rest = s;
+        while let Some(open) = rest.find('{') {
+            if rest[..open].contains('}') {
+                return Err(format!("unmatched `}}` in `{s}`"));
+            }
+            let close = rest[open..]
+                .find('}')
+                .ok_or_else(|| format!("unclosed `{{` in `{s}`"))?;
+            let placeholder = &rest[open + 1..open + close];
+            if !PLACEHOLDERS.contains(&placeholder) {
+                return Err(format!("unknown placeholder `{{{placeholder}}}` in `{s}`, expected {{stem}}, {{size}} or {{ext}}"));
+            }
+            rest = &rest[open + close + 1..];
+        }
+        if rest.contains('}') {
+            return Err(format!("unmatched `}}` in `{s}`"));
+        }
+        Ok(Self(s.to_string()))
+    }
+}
+
+/// What to do when the output file is already there.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Clobber {
+    /// Fail with [`DiscordifyError::OutputExists`] before transcoding anything.
+    #[default]
+    Refuse,
+    /// Replace it.
+    Overwrite,
+    /// Keep it and skip the job, the existing file is returned as the result.
+    Skip,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::size::MB;
+
+    fn template(s: &str) -> NameTemplate {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn renders_placeholders() {
+        let ten = ByteSize::from_bytes(10 * MB);
+        assert_eq!(NameTemplate::default().render(Path::new("videos/clip.mkv"), ten).unwrap(), "discord_ready_clip.mp4");
+        assert_eq!(template("{stem}_{size}MB.{ext}").render(Path::new("videos/clip.MKV"), ten).unwrap(), "clip_10MB.mkv");
+        assert_eq!(template("{stem}.{ext}").render(Path::new("clip"), ten).unwrap(), "clip.mp4");
+        assert_eq!(template("{size}").render(Path::new("clip.mp4"), ByteSize::from_bytes(24_500_000)).unwrap(), "24.5");
+        assert_eq!(template("{size}").render(Path::new("clip.mp4"), ByteSize::from_bytes(26_214_400)).unwrap(), "26.21");
+    }
+
+    #[test]
+    fn render_needs_a_file_name() {
+        let result = NameTemplate::default().render(Path::new(".."), ByteSize::from_bytes(MB));
+        assert!(matches!(result, Err(DiscordifyError::IncompletePath { .. })));
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        for s in ["", " ", "out/{stem}.mp4", "{stem", "stem}.mp4", "{}.mp4", "{name}.mp4"] {
+            assert!(s.parse::<NameTemplate>().is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn refuses_to_clobber_by_default() {
+        assert_eq!(Clobber::default(), Clobber::Refuse);
+    }
+
+    #[test]
+    fn recognizes_its_own_outputs() {
+        assert!(NameTemplate::default().is_output_name("discord_ready_clip.mp4"));
+        assert!(!NameTemplate::default().is_output_name("clip.mp4"));
+
+        let sized = template("{stem}_{size}MB.{ext}");
+        assert!(sized.is_output_name("clip_10MB.mkv"));
+        assert!(!sized.is_output_name("clip.mkv"));
+        assert!(!sized.is_output_name("clip_10MB"));
+    }
+
+    #[test]
+    fn template_without_text_matches_nothing() {
+        assert!(!template("{stem}.{ext}").is_output_name("clip.mp4"));
+        assert!(!template("{stem}").is_output_name("clip"));
+    }
+}
@@ -0,0 +1,162 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::error::{DiscordifyError, Result};
+use crate::size::{ByteSize, MB};
+
+// Upload limits as of writing. The user table can override these when Discord changes them.
+const BUILTIN: [(&str, u64); 5] = [
+    ("free", 10 * MB),
+    ("basic", 50 * MB),
+    ("nitro", 500 * MB),
+    ("server-boost-2", 50 * MB),
+    ("server-boost-3", 100 * MB),
+];
+
+// Aim a bit under the limit so container overhead and rounding never push a file over it.
+const DEFAULT_MARGIN: f64 = 0.05;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preset {
+    pub name: String,
+    pub limit: ByteSize,
+}
+
+/// Discord upload limits by tier, plus the safety margin to aim under them by.
+///
+/// The user table is a plain text file with one `name = size` per line (`100`, `100MB`, `95MiB`),
+/// `#` comments and an optional `margin = 5` line, in percent like `--margin`. Its entries are
+/// added to or replace the built-in ones.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Presets {
+    presets: Vec<Preset>,
+    margin: f64,
+}
+
+impl Default for Presets {
+    fn default() -> Self {
+        Self {
+            presets: BUILTIN
+                .iter()
+                .map(|&(name, bytes)| Preset { name: name.to_string(), limit: ByteSize::from_bytes(bytes) })
+                .collect(),
+            margin: DEFAULT_MARGIN,
+        }
+    }
+}
+
+impl Presets {
+    /// `$XDG_CONFIG_HOME/discordify/presets`, falling back to `~/.config/discordify/presets`.
+    pub fn default_path() -> Option<PathBuf> {
+        let config_dir = env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))?;
+        Some(config_dir.join("discordify").join("presets"))
+    }
+
+    /// The built-in table with the user table at `path` applied on top.
+    pub fn load(path: &Path) -> Result<Self> {
+        let mut presets = Self::default();
+        presets.apply(&fs::read_to_string(path)?)?;
+        Ok(presets)
+    }
+
+    /// Like [`Presets::load`] with the default path, but a missing file just means the built-in table.
+    pub fn load_default() -> Result<Self> {
+        match Self::default_path() {
+            Some(path) if path.is_file() => Self::load(&path),
+            _ => Ok(Self::default()),
+        }
+    }
+
+    pub fn apply(&mut self, table: &str) -> Result<()> {
+        for (number, line) in table.lines().enumerate() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let invalid = || DiscordifyError::InvalidPreset(format!("line {}: expected `name = size`, got `{line}`", number + 1));
+            let (name, value) = line.split_once('=').ok_or_else(invalid)?;
+            let name = name.trim().to_ascii_lowercase();
+
+            if name == "margin" {
+                let percent: f64 = value.trim().trim_end_matches('%').parse().map_err(|_| invalid())?;
+                if !(0.0..100.0).contains(&percent) {
+                    return Err(DiscordifyError::InvalidPreset(format!("line {}: margin must be at least 0 and below 100", number + 1)));
+                }
+                self.margin = percent / 100.0;
+                continue;
+            }
+            if name.is_empty() {
+                return Err(invalid());
+            }
+            let limit: ByteSize = value
+                .trim()
+                .parse()
+                .map_err(|e| DiscordifyError::InvalidPreset(format!("line {}: {e}", number + 1)))?;
+            match self.presets.iter_mut().find(|preset| preset.name == name) {
+                Some(preset) => preset.limit = limit,
+                None => self.presets.push(Preset { name, limit }),
+            }
+        }
+        Ok(())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Preset> {
+        self.presets.iter()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Preset> {
+        self.presets.iter().find(|preset| preset.name.eq_ignore_ascii_case(name))
+    }
+
+    /// Fraction to stay under a preset's limit by, 0.05 for 5%.
+    pub fn margin(&self) -> f64 {
+        self.margin
+    }
+
+    pub fn limit(&self, name: &str) -> Result<ByteSize> {
+        match self.get(name) {
+            Some(preset) => Ok(preset.limit),
+            None => {
+                let names: Vec<&str> = self.presets.iter().map(|preset| preset.name.as_str()).collect();
+                Err(DiscordifyError::InvalidPreset(format!("unknown preset `{name}`, expected one of: {}", names.join(", "))))
+            }
+        }
+    }
+
+    // Smallest and largest limit, the audio bitrate goes from lowest to highest across them.
+    pub(crate) fn limit_range(&self) -> (ByteSize, ByteSize) {
+        let smallest = self.presets.iter().map(|preset| preset.limit).min().unwrap_or_default();
+        let largest = self.presets.iter().map(|preset| preset.limit).max().unwrap_or_default();
+        if smallest < largest {
+            (smallest, largest)
+        } else {
+            (ByteSize::from_bytes(8 * MB), ByteSize::from_bytes(500 * MB))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn table_replaces_and_adds_tiers() {
+        let mut presets = Presets::default();
+        presets.apply("# mine\nFree = 25MB\nclassic = 100 # old nitro\n\nmargin = 10%\n").unwrap();
+        assert_eq!(presets.limit("free").unwrap(), ByteSize::from_bytes(25 * MB));
+        assert_eq!(presets.limit("Classic").unwrap(), ByteSize::from_bytes(100 * MB));
+        assert_eq!(presets.limit("nitro").unwrap(), ByteSize::from_bytes(500 * MB));
+        assert_eq!(presets.iter().count(), BUILTIN.len() + 1);
+        assert_eq!(presets.margin(), 0.1);
+    }
+
+    #[test]
+    fn rejects_bad_lines() {
+        for table in ["free 25MB", "= 25MB", "free = lots", "margin = 100", "margin = -1", "margin = some"] {
+            assert!(Presets::default().apply(table).is_err(), "{table}");
+        }
+    }
+}
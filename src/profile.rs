@@ -0,0 +1,39 @@
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Profile {
+    /// Output that plays inline in Discord: H.264 High unless another codec is picked,
+    /// yuv420p, AAC audio unless another codec is picked and an mp4 with the moov atom up front.
+    #[default]
+    EmbedSafe,
+    /// Keep the input's codec and pixel format, like discordify used to.
+    Source,
+}
+
+impl Profile {
+    pub fn name(self) -> &'static str {
+        match self {
+            Profile::EmbedSafe => "embed-safe",
+            Profile::Source => "source",
+        }
+    }
+}
+
+impl fmt::Display for Profile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Profile {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "embed-safe" | "embed" | "discord" => Ok(Profile::EmbedSafe),
+            "source" => Ok(Profile::Source),
+            _ => Err(format!("unknown profile `{s}`, expected embed-safe or source")),
+        }
+    }
+}
@@ -0,0 +1,222 @@
+use std::fmt;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::info;
+
+use crate::size::ByteSize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    Audio,
+    /// First pass of a two-pass encode, which only collects stats and writes nothing.
+    Analyze,
+    Video,
+}
+
+impl Stage {
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Audio => "audio",
+            Stage::Analyze => "analyze",
+            Stage::Video => "video",
+        }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Where a running job is at, handed to [`TranscodeOptions::progress`](crate::TranscodeOptions::progress)
+/// about once a second.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Progress {
+    pub stage: Stage,
+    /// Video pass of the size search, from 1. The audio is encoded once, as part of pass 1.
+    pub pass: u32,
+    pub frames: usize,
+    /// How far into the output the stage has got, in seconds.
+    pub out_time: f64,
+    /// Length of the output in seconds, 0 when the input doesn't say.
+    pub duration: f64,
+    /// How much of this stage is done, from 0 to 100.
+    pub percent: f64,
+    /// What the stage has written so far.
+    pub output_size: ByteSize,
+    /// Time spent on this stage.
+    pub elapsed: Duration,
+    /// Seconds of output encoded per second, 2.0 is twice as fast as playback.
+    pub speed: f64,
+    /// Time this pass still needs at the current speed, including the second half of a two-pass
+    /// encode while analyzing. Retries start from the speed of the pass before.
+    pub eta: Option<Duration>,
+}
+
+/// Called with every progress update instead of printing it.
+#[derive(Clone)]
+pub struct ProgressHandler(Arc<dyn Fn(&Progress) + Send + Sync>);
+
+impl ProgressHandler {
+    pub fn new(handler: impl Fn(&Progress) + Send + Sync + 'static) -> Self {
+        Self(Arc::new(handler))
+    }
+}
+
+impl fmt::Debug for ProgressHandler {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("ProgressHandler")
+    }
+}
+
+// What every stage of a job reports with: the handler, which pass it's on and the video speed
+// seen so far, shared by all its trackers so a retry can estimate before it has got anywhere.
+#[derive(Debug, Clone)]
+pub(crate) struct Reporter {
+    pub handler: Option<ProgressHandler>,
+    pub pass: u32,
+    video_speed: Arc<Mutex<Option<f64>>>,
+}
+
+impl Reporter {
+    pub fn new(handler: Option<ProgressHandler>) -> Self {
+        Self {
+            handler,
+            pass: 1,
+            video_speed: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    /// `label` is what the printed line starts with when there's no handler. `start` is where
+    /// in the input the output begins, taken off the timestamps to get the output time.
+    pub fn tracker(&self, stage: Stage, label: &'static str, start: f64, duration: f64, output: Option<PathBuf>) -> Tracker {
+        Tracker {
+            reporter: self.clone(),
+            stage,
+            label,
+            start,
+            duration,
+            output,
+            following: 0,
+            last_frames: 0,
+            started: Instant::now(),
+            last_report: Instant::now(),
+        }
+    }
+}
+
+// Progress of one stage, reported every 100 frames or every second, whichever comes first.
+pub(crate) struct Tracker {
+    reporter: Reporter,
+    stage: Stage,
+    label: &'static str,
+    start: f64,
+    duration: f64,
+    output: Option<PathBuf>,
+    following: u32,
+    last_frames: usize,
+    started: Instant,
+    last_report: Instant,
+}
+
+impl Tracker {
+    /// Counts `stages` more runs over the same input into the ETA, like the second pass after
+    /// the analysis pass.
+    pub fn followed_by(mut self, stages: u32) -> Self {
+        self.following = stages;
+        self
+    }
+
+    /// `time` is the input timestamp of the latest frame in seconds.
+    pub fn update(&mut self, frames: usize, time: Option<f64>) {
+        if frames - self.last_frames < 100 && self.last_report.elapsed().as_secs_f64() < 1.0 {
+            return;
+        }
+        self.last_frames = frames;
+        self.last_report = Instant::now();
+
+        let out_time = time.map_or(0.0, |time| (time - self.start).max(0.0));
+        let fraction = if self.duration > 0.0 { (out_time / self.duration).min(1.0) } else { 0.0 };
+        let elapsed = self.started.elapsed();
+        let speed = if elapsed.as_secs_f64() > 0.0 { out_time / elapsed.as_secs_f64() } else { 0.0 };
+        let progress = Progress {
+            stage: self.stage,
+            pass: self.reporter.pass,
+            frames,
+            out_time,
+            duration: self.duration,
+            percent: fraction * 100.0,
+            output_size: self.output_size(),
+            elapsed,
+            speed,
+            eta: self.eta(fraction, speed),
+        };
+        match &self.reporter.handler {
+            Some(handler) => handler.0(&progress),
+            None => self.print(&progress),
+        }
+    }
+
+    fn eta(&self, fraction: f64, speed: f64) -> Option<Duration> {
+        if self.duration <= 0.0 {
+            return None;
+        }
+        let mut video_speed = self.reporter.video_speed.lock().unwrap_or_else(|e| e.into_inner());
+        // The first percent is mostly opening files and filling the encoder, too early to go by.
+        let speed = if fraction > 0.01 {
+            if self.stage != Stage::Audio {
+                *video_speed = Some(speed);
+            }
+            speed
+        } else if self.stage != Stage::Audio {
+            (*video_speed)?
+        } else {
+            return None;
+        };
+        if speed <= 0.0 {
+            return None;
+        }
+        let remaining = self.duration * (1.0 - fraction) + self.duration * self.following as f64;
+        Some(Duration::from_secs_f64(remaining / speed))
+    }
+
+    fn output_size(&self) -> ByteSize {
+        self.output
+            .as_ref()
+            .and_then(|output| fs::metadata(output).ok())
+            .map(|meta| ByteSize::from_bytes(meta.len()))
+            .unwrap_or_default()
+    }
+
+    fn print(&self, progress: &Progress) {
+        let label = match (self.stage, progress.pass) {
+            (Stage::Audio, _) | (_, 1) => self.label.to_string(),
+            (_, pass) => format!("{} (pass {pass})", self.label),
+        };
+        let percent = match self.duration > 0.0 {
+            true => format!("{:5.1}%", progress.percent),
+            false => "    ?%".to_string(),
+        };
+        info!(
+            "{label} {percent}\tTIMESTAMP: {}/{}\tSPEED: {:5.2}x\tETA: {}\tFRAMES: {:8}\tELAPSED: {:8.2}s",
+            clock(progress.out_time),
+            clock(progress.duration),
+            progress.speed,
+            progress.eta.map_or("--:--".to_string(), |eta| clock(eta.as_secs_f64())),
+            progress.frames,
+            progress.elapsed.as_secs_f64(),
+        );
+    }
+}
+
+// mm:ss, or h:mm:ss from an hour up.
+fn clock(secs: f64) -> String {
+    let total = secs.max(0.0) as u64;
+    match total {
+        0..3600 => format!("{:02}:{:02}", total / 60, total % 60),
+        _ => format!("{}:{:02}:{:02}", total / 3600, (total % 3600) / 60, total % 60),
+    }
+}
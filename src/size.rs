@@ -0,0 +1,124 @@
+use std::fmt;
+use std::str::FromStr;
+
+pub(crate) const KB: u64 = 1000;
+pub(crate) const MB: u64 = 1000 * KB;
+pub(crate) const GB: u64 = 1000 * MB;
+const KIB: u64 = 1024;
+const MIB: u64 = 1024 * KIB;
+const GIB: u64 = 1024 * MIB;
+
+/// A file size in bytes.
+///
+/// Parses `25MB`, `25MiB`, `26214400B` or `24.5M`, and a bare number as MB. KB, MB and GB
+/// (and K, M and G) are decimal like Discord's upload limits, KiB, MiB and GiB are binary.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct ByteSize(u64);
+
+impl ByteSize {
+    pub const fn from_bytes(bytes: u64) -> Self {
+        Self(bytes)
+    }
+
+    pub fn from_mb(mb: f64) -> Self {
+        Self((mb * MB as f64).round() as u64)
+    }
+
+    pub const fn bytes(self) -> u64 {
+        self.0
+    }
+
+    /// Decimal megabytes, for display and the rough scales that don't need exact bytes.
+    pub fn as_mb(self) -> f64 {
+        self.0 as f64 / MB as f64
+    }
+
+    /// The size with `margin` (0.05 for 5%) taken off, rounded down to whole bytes.
+    pub fn with_margin(self, margin: f64) -> Self {
+        let cut = (self.0 as f64 * margin.clamp(0.0, 1.0)).ceil() as u64;
+        Self(self.0.saturating_sub(cut))
+    }
+
+    pub const fn saturating_sub(self, other: ByteSize) -> Self {
+        Self(self.0.saturating_sub(other.0))
+    }
+}
+
+impl fmt::Display for ByteSize {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:.2} MB", self.as_mb())
+    }
+}
+
+impl FromStr for ByteSize {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let split = s.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(s.len());
+        let (number, unit) = (s[..split].trim(), s[split..].trim());
+        let multiplier = match unit.to_ascii_lowercase().as_str() {
+            "" | "m" | "mb" => MB,
+            "b" => 1,
+            "k" | "kb" => KB,
+            "g" | "gb" => GB,
+            "kib" => KIB,
+            "mib" => MIB,
+            "gib" => GIB,
+            _ => return Err(format!("unknown size unit `{unit}` in `{s}`, expected B, KB, MB, GB, KiB, MiB or GiB")),
+        };
+
+        // Whole numbers stay exact, fractions go through f64 and get rounded to the byte.
+        let bytes = match number.parse::<u64>() {
+            Ok(value) => value.checked_mul(multiplier),
+            Err(_) => match number.parse::<f64>() {
+                Ok(value) if value.is_finite() && value >= 0.0 && value * (multiplier as f64) < u64::MAX as f64 => {
+                    Some((value * multiplier as f64).round() as u64)
+                }
+                _ => return Err(format!("`{s}` is not a size")),
+            },
+        };
+        match bytes {
+            Some(bytes) if bytes > 0 => Ok(Self(bytes)),
+            Some(_) => Err(format!("size must be greater than 0, got `{s}`")),
+            None => Err(format!("`{s}` is too big")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn size(s: &str) -> Result<ByteSize, String> {
+        s.parse()
+    }
+
+    #[test]
+    fn parses_decimal_and_binary_units() {
+        assert_eq!(size("25MB"), Ok(ByteSize::from_bytes(25 * MB)));
+        assert_eq!(size("25"), Ok(ByteSize::from_bytes(25 * MB)));
+        assert_eq!(size("25MiB"), Ok(ByteSize::from_bytes(25 * MIB)));
+        assert_eq!(size("26214400B"), Ok(ByteSize::from_bytes(26_214_400)));
+        assert_eq!(size(" 2 gb "), Ok(ByteSize::from_bytes(2 * GB)));
+        assert_eq!(size("24.5M"), Ok(ByteSize::from_bytes(24_500_000)));
+        assert_eq!(size("1.5KiB"), Ok(ByteSize::from_bytes(1536)));
+    }
+
+    #[test]
+    fn rejects_what_is_not_a_size() {
+        for s in ["0", "0.0MB", "-1MB", "MB", "10TB", "ten", "20000000000GB"] {
+            assert!(size(s).is_err(), "{s}");
+        }
+    }
+
+    #[test]
+    fn margin_rounds_down_and_stays_in_range() {
+        let size = ByteSize::from_bytes(1000);
+        assert_eq!(size.with_margin(0.05), ByteSize::from_bytes(950));
+        assert_eq!(size.with_margin(0.0015), ByteSize::from_bytes(998));
+        assert_eq!(size.with_margin(0.0), size);
+        assert_eq!(size.with_margin(-1.0), size);
+        assert_eq!(size.with_margin(2.0), ByteSize::from_bytes(0));
+    }
+}
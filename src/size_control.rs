@@ -0,0 +1,182 @@
+// Picks the size to ask the video encoder for on each pass, based on how far off the previous
+// passes landed. Audio is a fixed cost, so the correction is only applied to the video part.
+// The first pass may be asked for while the audio is still encoding, around its expected size,
+// the actual one replaces it once it's known.
+
+use crate::size::ByteSize;
+
+const SAFETY: f64 = 0.98;
+
+#[derive(Debug, Clone, Copy)]
+pub struct PassRecord {
+    pub requested: ByteSize,
+    pub actual: ByteSize,
+    /// The audio size the request was worked out around.
+    pub audio: ByteSize,
+}
+
+pub enum Verdict {
+    Done,
+    Retry(ByteSize),
+    GiveUp,
+}
+
+pub struct SizeController {
+    target: ByteSize,
+    audio: ByteSize,
+    requested_audio: ByteSize,
+    max_passes: u32,
+    history: Vec<PassRecord>,
+}
+
+impl SizeController {
+    pub fn new(target: ByteSize, audio: ByteSize, max_passes: u32) -> Self {
+        Self {
+            target,
+            audio,
+            requested_audio: audio,
+            max_passes: max_passes.max(1),
+            history: Vec::new(),
+        }
+    }
+
+    pub fn first_request(&self) -> ByteSize {
+        self.target
+    }
+
+    pub fn passes(&self) -> u32 {
+        self.history.len() as u32
+    }
+
+    pub fn best(&self) -> Option<ByteSize> {
+        self.history.iter().map(|record| record.actual).min()
+    }
+
+    pub fn set_audio_size(&mut self, audio: ByteSize) {
+        self.audio = audio;
+    }
+
+    pub fn record(&mut self, requested: ByteSize, actual: ByteSize) -> Verdict {
+        self.history.push(PassRecord { requested, actual, audio: self.requested_audio });
+
+        if actual <= self.target {
+            return Verdict::Done;
+        }
+        if self.passes() >= self.max_passes {
+            return Verdict::GiveUp;
+        }
+
+        match self.next_request() {
+            Some(next) if next > self.audio => {
+                self.requested_audio = self.audio;
+                Verdict::Retry(next)
+            }
+            _ => Verdict::GiveUp,
+        }
+    }
+
+    // Sizes are whole bytes, only the video part gets turned into f64 to interpolate on.
+    fn video_bytes(size: ByteSize, audio: ByteSize) -> f64 {
+        size.bytes() as f64 - audio.bytes() as f64
+    }
+
+    // What a pass asked the video encoder for and what it wrote, without the audio. Every
+    // output carries the final audio, a request may have been made around the expected one.
+    fn video_part(&self, record: &PassRecord) -> (f64, f64) {
+        (Self::video_bytes(record.requested, record.audio), Self::video_bytes(record.actual, self.audio))
+    }
+
+    fn next_request(&self) -> Option<ByteSize> {
+        let wanted_video = Self::video_bytes(self.target, self.audio) * SAFETY;
+        let (last_requested, last_actual) = self.video_part(self.history.last()?);
+
+        // With two passes we can see how the output responds to the request and interpolate,
+        // otherwise assume the overshoot ratio stays the same.
+        let mut next_video = match self.history.iter().rev().nth(1) {
+            Some(previous) => {
+                let (previous_requested, previous_actual) = self.video_part(previous);
+                let slope = (last_actual - previous_actual) / (last_requested - previous_requested);
+                if slope.is_finite() && slope > 0.0 {
+                    last_requested + (wanted_video - last_actual) / slope
+                } else {
+                    last_requested * wanted_video / last_actual
+                }
+            }
+            None => last_requested * wanted_video / last_actual,
+        };
+
+        // Never ask for more than a request that already overshot.
+        let smallest_overshooting = self
+            .history
+            .iter()
+            .filter(|record| record.actual > self.target)
+            .map(|record| self.video_part(record).0)
+            .reduce(f64::min)?;
+        if next_video >= smallest_overshooting {
+            next_video = smallest_overshooting * SAFETY;
+        }
+
+        if !next_video.is_finite() || next_video < 1.0 {
+            return None;
+        }
+        Some(ByteSize::from_bytes(self.audio.bytes() + next_video as u64))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::size::MB;
+
+    fn mb(mb: u64) -> ByteSize {
+        ByteSize::from_bytes(mb * MB)
+    }
+
+    #[test]
+    fn done_once_under_the_target() {
+        let mut controller = SizeController::new(mb(10), mb(1), 3);
+        assert_eq!(controller.first_request(), mb(10));
+        assert!(matches!(controller.record(mb(10), mb(9)), Verdict::Done));
+        assert_eq!(controller.passes(), 1);
+    }
+
+    #[test]
+    fn asks_for_less_after_overshooting() {
+        let mut controller = SizeController::new(mb(10), mb(1), 3);
+        let Verdict::Retry(second) = controller.record(mb(10), mb(12)) else {
+            panic!("expected a retry");
+        };
+        assert!(second < mb(10));
+        let Verdict::Retry(third) = controller.record(second, mb(11)) else {
+            panic!("expected a retry");
+        };
+        assert!(third < second);
+    }
+
+    #[test]
+    fn gives_up_after_the_last_pass() {
+        let mut controller = SizeController::new(mb(10), mb(1), 2);
+        assert!(matches!(controller.record(mb(10), mb(12)), Verdict::Retry(_)));
+        assert!(matches!(controller.record(mb(9), mb(11)), Verdict::GiveUp));
+        assert_eq!(controller.passes(), 2);
+        assert_eq!(controller.best(), Some(mb(11)));
+    }
+
+    #[test]
+    fn gives_up_when_the_audio_leaves_no_room() {
+        let mut controller = SizeController::new(mb(10), ByteSize::from_bytes(10 * MB - 10), 3);
+        assert!(matches!(controller.record(mb(10), mb(20)), Verdict::GiveUp));
+    }
+
+    #[test]
+    fn corrects_with_the_actual_audio_size() {
+        // Asked for around 1MB of expected audio, which came out at 2MB: the video part was
+        // on the mark, only the audio overshot.
+        let mut controller = SizeController::new(mb(10), mb(1), 3);
+        controller.set_audio_size(mb(2));
+        let Verdict::Retry(next) = controller.record(mb(10), mb(11)) else {
+            panic!("expected a retry");
+        };
+        assert!(next > ByteSize::from_bytes(9_800_000) && next < mb(10), "{next}");
+    }
+}
@@ -0,0 +1,63 @@
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use tempfile::TempDir;
+use tracing::info;
+
+use crate::error::Result;
+
+// Job folders that still exist, so an interrupted run can remove them on its way out.
+static ACTIVE: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Folder for one job's intermediate files, removed with everything in it when dropped
+/// unless it's kept.
+pub(crate) enum JobDir {
+    Temporary(TempDir),
+    Kept(PathBuf),
+}
+
+impl JobDir {
+    /// A new `discordify-*` folder in `base`, or in the system temp folder when `None`.
+    pub fn create(base: Option<&Path>, keep: bool) -> Result<Self> {
+        let base = base.map(Path::to_path_buf).unwrap_or_else(env::temp_dir);
+        fs::create_dir_all(&base)?;
+        let dir = tempfile::Builder::new().prefix("discordify-").tempdir_in(&base)?;
+        if keep {
+            let path = dir.keep();
+            info!("Keeping intermediate files in {}", path.display());
+            return Ok(Self::Kept(path));
+        }
+        ACTIVE.lock().unwrap_or_else(PoisonError::into_inner).push(dir.path().to_path_buf());
+        Ok(Self::Temporary(dir))
+    }
+
+    pub fn path(&self) -> &Path {
+        match self {
+            Self::Temporary(dir) => dir.path(),
+            Self::Kept(path) => path,
+        }
+    }
+
+    pub fn is_kept(&self) -> bool {
+        matches!(self, Self::Kept(_))
+    }
+}
+
+impl Drop for JobDir {
+    fn drop(&mut self) {
+        if let Self::Temporary(dir) = self {
+            ACTIVE.lock().unwrap_or_else(PoisonError::into_inner).retain(|path| path != dir.path());
+        }
+    }
+}
+
+/// Removes the intermediate files of every job that is still running.
+///
+/// Dropping a job cleans up after it, this is for when the process is about to exit without
+/// unwinding, like from a Ctrl-C handler.
+pub fn remove_temp_dirs() {
+    for path in ACTIVE.lock().unwrap_or_else(PoisonError::into_inner).drain(..) {
+        let _ = fs::remove_dir_all(path);
+    }
+}
@@ -0,0 +1,118 @@
+use ffmpeg_next as ffmpeg;
+use ffmpeg::{format, Rational};
+
+use crate::error::{DiscordifyError, Result};
+
+/// Slice of the input to keep, in seconds. Everything unset keeps the whole input.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Trim {
+    pub start: Option<f64>,
+    pub end: Option<f64>,
+    /// How much to keep after `start`, instead of giving an `end`.
+    pub duration: Option<f64>,
+}
+
+impl Trim {
+    pub fn is_empty(&self) -> bool {
+        self.start.is_none() && self.end.is_none() && self.duration.is_none()
+    }
+
+    pub(crate) fn range(&self, input_duration: f64) -> Result<Option<TimeRange>> {
+        if self.is_empty() {
+            return Ok(None);
+        }
+        let start = self.start.unwrap_or(0.0);
+        let end = match (self.end, self.duration) {
+            (Some(_), Some(_)) => return Err(DiscordifyError::InvalidTrim("give either an end or a duration, not both".to_string())),
+            (Some(end), None) => end,
+            (None, Some(duration)) => start + duration,
+            (None, None) => input_duration,
+        };
+        // Containers without a duration report 0, let the input run out on its own then.
+        let end = if input_duration > 0.0 { end.min(input_duration) } else { end };
+
+        if start < 0.0 || !start.is_finite() || !end.is_finite() {
+            return Err(DiscordifyError::InvalidTrim(format!("{start}s to {end}s is not a valid range")));
+        }
+        if start >= end {
+            return Err(DiscordifyError::InvalidTrim(format!("start {start}s is not before end {end}s (input is {input_duration:.2}s long)")));
+        }
+        Ok(Some(TimeRange { start, end }))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) struct TimeRange {
+    pub start: f64,
+    pub end: f64,
+}
+
+impl TimeRange {
+    pub fn duration(&self) -> f64 {
+        self.end - self.start
+    }
+
+    pub fn contains(&self, time: f64) -> bool {
+        time >= self.start && time < self.end
+    }
+
+    // The start in a stream's time base, to shift its timestamps back to zero.
+    pub fn start_in(&self, time_base: Rational) -> i64 {
+        (self.start / f64::from(time_base)).round() as i64
+    }
+}
+
+// Jumps to the keyframe before the start, the decoders drop whatever comes before it.
+pub(crate) fn seek(input_context: &mut format::context::Input, range: &TimeRange) -> Result<()> {
+    if range.start > 0.0 {
+        let timestamp = (range.start * f64::from(ffmpeg::ffi::AV_TIME_BASE)) as i64;
+        input_context.seek(timestamp, ..=timestamp)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn seconds(timestamp: Option<i64>, time_base: Rational) -> Option<f64> {
+    timestamp.map(|timestamp| timestamp as f64 * f64::from(time_base))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trim(start: Option<f64>, end: Option<f64>, duration: Option<f64>) -> Trim {
+        Trim { start, end, duration }
+    }
+
+    fn range(start: f64, end: f64) -> Option<TimeRange> {
+        Some(TimeRange { start, end })
+    }
+
+    #[test]
+    fn empty_keeps_the_whole_input() {
+        assert_eq!(Trim::default().range(60.0).unwrap(), None);
+    }
+
+    #[test]
+    fn ends_at_the_end_the_duration_or_the_input() {
+        assert_eq!(trim(Some(10.0), Some(20.0), None).range(60.0).unwrap(), range(10.0, 20.0));
+        assert_eq!(trim(Some(10.0), None, Some(5.0)).range(60.0).unwrap(), range(10.0, 15.0));
+        assert_eq!(trim(Some(10.0), None, None).range(60.0).unwrap(), range(10.0, 60.0));
+        assert_eq!(trim(None, Some(30.0), None).range(60.0).unwrap(), range(0.0, 30.0));
+        assert_eq!(trim(Some(50.0), None, Some(30.0)).range(60.0).unwrap(), range(50.0, 60.0));
+        // No known duration, the input runs out on its own.
+        assert_eq!(trim(None, Some(30.0), None).range(0.0).unwrap(), range(0.0, 30.0));
+    }
+
+    #[test]
+    fn rejects_bad_ranges() {
+        for bad in [
+            trim(Some(10.0), Some(20.0), Some(5.0)),
+            trim(Some(-1.0), None, None),
+            trim(Some(f64::NAN), None, None),
+            trim(Some(20.0), Some(10.0), None),
+            trim(Some(70.0), None, None),
+        ] {
+            assert!(matches!(bad.range(60.0), Err(DiscordifyError::InvalidTrim(_))), "{bad:?}");
+        }
+    }
+}
@@ -1,6 +1,7 @@
 use std::{env, fs};
 use std::path::PathBuf;
 use std::process::{Command};
+use tracing::{info, warn};
 
 fn is_wlcopy_available() -> bool {
     Command::new("wl-copy").arg("-version").output().map_or(false, |output| output.status.success())
@@ -10,44 +11,42 @@ fn is_wayland() -> bool {
     env::var("WAYLAND_DISPLAY").is_ok()
 }
 
-pub fn copy_video_file(file_path:&PathBuf) {
+pub fn copy_video_files(file_paths: &[PathBuf]) {
     #[cfg(not(target_os = "linux"))]
     {
-        eprintln!("Copying video-file feature is only supported on Linux systems.. Continuing without it..");
+        warn!("Copying video-file feature is only supported on Linux systems.. Continuing without it..");
         return;
     }
     #[cfg(target_os = "linux")]
     {
         if !is_wayland() {
-            eprintln!("Copying video-file feature is only supported on Wayland systems.. Continuing without it..");
+            warn!("Copying video-file feature is only supported on Wayland systems.. Continuing without it..");
             return;
         }
         if !is_wlcopy_available() {
-            eprintln!("wlcopy is not available. Please install wl-clipboard.. Continuing without it..");
+            warn!("wlcopy is not available. Please install wl-clipboard.. Continuing without it..");
             return;
         }
     
-        match fs::canonicalize(&file_path) {
-            Ok(absolute_path) => {
-                let video_file_path = absolute_path.to_str().unwrap().to_string();
-
-                let command = format!("echo -n 'file://{}' | wl-copy -t text/uri-list", video_file_path);
-
-                let status = Command::new("sh")
-                    .arg("-c")
-                    .arg(command)
-                    .status()
-                    .expect("Failed to execute command");
-
-                if !status.success() {
-                    eprintln!("Command failed with status: {}", status);
-                } else {
-                    println!("Video successfully copied to clipboard..");
+        let mut uris = Vec::new();
+        for file_path in file_paths {
+            match fs::canonicalize(file_path) {
+                Ok(absolute_path) => uris.push(format!("file://{}", absolute_path.display())),
+                Err(_) => {
+                    warn!("Failed to get absolute path for the video file: {}", file_path.display());
+                    return;
                 }
             }
-            Err(_) => {
-                eprintln!("Failed to get absolute path for the video file: {}", file_path.display());
-            }
+        }
+        if uris.is_empty() {
+            return;
+        }
+
+        // text/uri-list wants one uri per line, so split parts all get pasted together.
+        match Command::new("wl-copy").arg("-t").arg("text/uri-list").arg(uris.join("\r\n")).status() {
+            Ok(status) if status.success() => info!("Video successfully copied to clipboard.."),
+            Ok(status) => warn!("Command failed with status: {}", status),
+            Err(e) => warn!("Failed to execute command: {}", e),
         }
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,176 @@
+use std::fmt;
+use std::str::FromStr;
+
+use crate::error::{DiscordifyError, Result};
+use crate::profile::Profile;
+use crate::size::ByteSize;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoCodec {
+    H264,
+    Hevc,
+    Vp9,
+    /// Whichever AV1 encoder is available, preferring SVT-AV1.
+    Av1,
+    Av1Aom,
+    Av1Svt,
+    Av1Rav1e,
+}
+
+impl VideoCodec {
+    pub const ALL: [VideoCodec; 7] = [
+        VideoCodec::H264,
+        VideoCodec::Hevc,
+        VideoCodec::Vp9,
+        VideoCodec::Av1,
+        VideoCodec::Av1Aom,
+        VideoCodec::Av1Svt,
+        VideoCodec::Av1Rav1e,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            VideoCodec::H264 => "h264",
+            VideoCodec::Hevc => "hevc",
+            VideoCodec::Vp9 => "vp9",
+            VideoCodec::Av1 => "av1",
+            VideoCodec::Av1Aom => "av1-aom",
+            VideoCodec::Av1Svt => "av1-svt",
+            VideoCodec::Av1Rav1e => "av1-rav1e",
+        }
+    }
+
+    /// ffmpeg encoders for this codec, in order of preference.
+    pub fn encoder_names(self) -> &'static [&'static str] {
+        match self {
+            VideoCodec::H264 => &["libx264"],
+            VideoCodec::Hevc => &["libx265"],
+            VideoCodec::Vp9 => &["libvpx-vp9"],
+            VideoCodec::Av1 => &["libsvtav1", "libaom-av1", "librav1e"],
+            VideoCodec::Av1Aom => &["libaom-av1"],
+            VideoCodec::Av1Svt => &["libsvtav1"],
+            VideoCodec::Av1Rav1e => &["librav1e"],
+        }
+    }
+}
+
+impl fmt::Display for VideoCodec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for VideoCodec {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "h264" | "avc" | "x264" | "libx264" => Ok(VideoCodec::H264),
+            "hevc" | "h265" | "x265" | "libx265" => Ok(VideoCodec::Hevc),
+            "vp9" | "libvpx-vp9" => Ok(VideoCodec::Vp9),
+            "av1" => Ok(VideoCodec::Av1),
+            "av1-aom" | "libaom-av1" => Ok(VideoCodec::Av1Aom),
+            "av1-svt" | "svt-av1" | "libsvtav1" => Ok(VideoCodec::Av1Svt),
+            "av1-rav1e" | "rav1e" | "librav1e" => Ok(VideoCodec::Av1Rav1e),
+            _ => {
+                let names: Vec<&str> = VideoCodec::ALL.iter().map(|codec| codec.name()).collect();
+                Err(format!("unknown video codec `{s}`, expected one of: {}", names.join(", ")))
+            }
+        }
+    }
+}
+
+// Video codecs the output container can carry, the embed-safe pick first.
+fn container_codecs(extension: &str) -> &'static [VideoCodec] {
+    match extension.to_ascii_lowercase().as_str() {
+        "webm" => &[
+            VideoCodec::Vp9,
+            VideoCodec::Av1,
+            VideoCodec::Av1Aom,
+            VideoCodec::Av1Svt,
+            VideoCodec::Av1Rav1e,
+        ],
+        // mp4, mov and mkv: H.264 is what every Discord client plays inline.
+        _ => &VideoCodec::ALL,
+    }
+}
+
+// `None` keeps the input's codec, which only the source profile does when none was asked for.
+pub(crate) fn resolve(video_codec: Option<VideoCodec>, profile: Profile, extension: &str) -> Result<Option<VideoCodec>> {
+    let allowed = container_codecs(extension);
+    match video_codec {
+        Some(codec) if allowed.contains(&codec) => Ok(Some(codec)),
+        Some(codec) => Err(DiscordifyError::IncompatibleCodec {
+            codec: codec.name().to_string(),
+            container: extension.to_string(),
+        }),
+        None if profile == Profile::EmbedSafe => Ok(Some(allowed[0])),
+        None => Ok(None),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum TwoPass {
+    /// libx264 `stats` option together with the PASS1/PASS2 codec flags.
+    X264,
+    /// `pass`/`stats` handed to libx265 through `x265-params`.
+    X265,
+}
+
+pub(crate) fn two_pass(encoder_name: &str) -> Option<TwoPass> {
+    match encoder_name {
+        "libx264" => Some(TwoPass::X264),
+        "libx265" => Some(TwoPass::X265),
+        // libvpx, libaom and rav1e only do two-pass through stats_out/stats_in on the codec
+        // context, so those stay single pass and lean on the size controller instead.
+        _ => None,
+    }
+}
+
+// Slower presets on small inputs where it's cheap, faster ones once the input gets big.
+pub(crate) fn encoder_options(encoder_name: &str, input_file_size: ByteSize) -> &'static str {
+    let tier = match input_file_size.as_mb() {
+        1.0..=200.0 => 0,
+        200.0..=500.0 => 1,
+        _ => 2,
+    };
+    match (encoder_name, tier) {
+        ("libx264", 0) => "preset=slow",
+        ("libx264", 1) => "preset=medium",
+        ("libx264", _) => "preset=fast",
+        ("libx265", 0) => "preset=medium",
+        ("libx265", 1) => "preset=fast",
+        ("libx265", _) => "preset=veryfast",
+        ("libvpx-vp9", 0) => "deadline=good,cpu-used=2,row-mt=1",
+        ("libvpx-vp9", 1) => "deadline=good,cpu-used=3,row-mt=1",
+        ("libvpx-vp9", _) => "deadline=good,cpu-used=5,row-mt=1",
+        ("libaom-av1", 0) => "cpu-used=4,row-mt=1,end-usage=vbr",
+        ("libaom-av1", 1) => "cpu-used=5,row-mt=1,end-usage=vbr",
+        ("libaom-av1", _) => "cpu-used=6,row-mt=1,end-usage=vbr",
+        ("libsvtav1", 0) => "preset=6",
+        ("libsvtav1", 1) => "preset=8",
+        ("libsvtav1", _) => "preset=10",
+        ("librav1e", 0) => "speed=6",
+        ("librav1e", 1) => "speed=8",
+        ("librav1e", _) => "speed=10",
+        _ => "",
+    }
+}
+
+// A max rate equal to the bitrate turns SVT-AV1 and libvpx into CBR, so only the x26x encoders get one.
+pub(crate) fn caps_max_bit_rate(encoder_name: &str) -> bool {
+    matches!(encoder_name, "libx264" | "libx265")
+}
+
+pub(crate) fn embed_safe_options(encoder_name: &str) -> &'static str {
+    match encoder_name {
+        "libx264" => "profile=high",
+        "libx265" => "profile=main",
+        _ => "",
+    }
+}
+
+// Discord's desktop and browser clients don't inline-play HEVC.
+pub(crate) fn embeds_in_discord(encoder_name: &str) -> bool {
+    encoder_name != "libx265"
+}
@@ -2,194 +2,602 @@ use ffmpeg_next as ffmpeg;
 
 //video
 use ffmpeg::{
-    codec, decoder, encoder, format, frame, media, picture, Dictionary, Packet, Rational
+    codec, decoder, encoder, format, frame, media, picture, Codec, Dictionary, Packet, Rational
 };
-use std::collections::HashMap;
-use std::{env, fs};
+use ffmpeg::software::scaling;
+use std::ffi::{CString, OsString};
+use std::fs;
 use std::fs::metadata;
-use std::path::PathBuf;
-use std::time::Instant;
-use crate::OVERRIDDEN_PATH;
+use std::iter::Peekable;
+use std::ptr;
+use std::path::{Path, PathBuf};
+use tracing::{info, warn};
+
+use crate::audio_transcode::PendingAudio;
+use crate::budget;
+use crate::cancel::CancelToken;
+use crate::error::{DiscordifyError, Result};
+use crate::profile::Profile;
+use crate::progress::{Reporter, Stage, Tracker};
+use crate::size::ByteSize;
+use crate::trim::{self, TimeRange};
+use crate::video_codec::{self, TwoPass, VideoCodec};
+
+#[derive(Debug, Clone, Default)]
+pub struct VideoOptions {
+    pub video_codec: Option<VideoCodec>,
+    pub profile: Profile,
+    /// Fixed height cap instead of picking the resolution from the bitrate.
+    pub max_height: Option<u32>,
+    /// Fixed frame rate cap instead of picking it from the bitrate.
+    pub max_fps: Option<f64>,
+    /// Part of the input to keep, `None` for all of it.
+    pub trim: Option<TimeRange>,
+}
+
+pub struct OutputShape {
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate: f64,
+}
+
+impl VideoOptions {
+    // The embed-safe profile falls back to H.264 rather than whatever the input used.
+    pub fn resolved_codec(&self) -> Option<VideoCodec> {
+        match self.profile {
+            Profile::EmbedSafe => self.video_codec.or(Some(VideoCodec::H264)),
+            Profile::Source => self.video_codec,
+        }
+    }
+
+    pub fn output_shape(&self, probe: &VideoProbe, bit_rate: usize) -> OutputShape {
+        let min_bits_per_pixel = budget::min_bits_per_pixel(self.resolved_codec());
+        let frame_rate = budget::output_frame_rate(
+            probe.width,
+            probe.height,
+            probe.frame_rate,
+            bit_rate,
+            min_bits_per_pixel,
+            self.max_fps,
+        );
+        let (width, height) = budget::output_size(
+            probe.width,
+            probe.height,
+            frame_rate,
+            bit_rate,
+            min_bits_per_pixel,
+            self.max_height,
+        );
+        OutputShape { width, height, frame_rate }
+    }
+}
+
+pub struct VideoProbe {
+    pub width: u32,
+    pub height: u32,
+    pub frame_rate: f64,
+    pub codec_id: codec::Id,
+}
+
+pub fn probe(input_context: &format::context::Input) -> Result<VideoProbe> {
+    let stream = input_context
+        .streams()
+        .best(media::Type::Video)
+        .ok_or(DiscordifyError::NoStream("video"))?;
+    let decoder = codec::context::Context::from_parameters(stream.parameters())?
+        .decoder()
+        .video()?;
+    let frame_rate = decoder
+        .frame_rate()
+        .into_iter()
+        .chain([stream.avg_frame_rate()])
+        .find(|rate| rate.numerator() > 0 && rate.denominator() > 0)
+        .map(f64::from)
+        .unwrap_or(30.0);
+
+    Ok(VideoProbe {
+        width: decoder.width(),
+        height: decoder.height(),
+        frame_rate,
+        codec_id: stream.parameters().id(),
+    })
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Pass {
+    Single,
+    First,
+    Second,
+}
+
+#[derive(Clone, Copy)]
+struct EncodeSettings {
+    codec: Codec,
+    bit_rate: usize,
+    profile: Profile,
+    width: u32,
+    height: u32,
+    /// Set when frames get dropped to reach a lower frame rate.
+    frame_rate: Option<f64>,
+    trim: Option<TimeRange>,
+    /// Length of the output in seconds.
+    duration: f64,
+}
 
 struct VideoTranscoder {
     output_stream_index: usize,
     decoder: decoder::Video,
     input_time_base: Rational,
     video_encoder: encoder::Video,
-    logging_enabled: bool,
+    scaler: Option<scaling::Context>,
+    frame_interval: Option<f64>,
+    next_frame_time: Option<f64>,
+    trim: Option<TimeRange>,
     frame_count: usize,
-    last_log_frame_count: usize,
-    starting_time: Instant,
-    last_log_time: Instant,
-    output_path: PathBuf,
-    wanted_size: f32,
-    actual_start_time:Instant
+    progress: Tracker,
 }
 
-pub async fn video(input_file: PathBuf, audio_path: &Option<PathBuf>, output_path: PathBuf, wanted_size:&f32, actual_start_time:Instant) -> PathBuf {
-    let mut audio_file_size = 0.0;
+// The audio may still be encoding, the bitrate is worked out from its expected size and it's
+// only waited for once the output needs it: after the analysis pass of a two-pass encode, after
+// the video of a single-pass one, which is then muxed with it into the output.
+pub fn video(input_file: PathBuf, audio: &mut PendingAudio, output_path: PathBuf, wanted_size: ByteSize, options: &VideoOptions, reporter: &Reporter, cancel: &CancelToken) -> Result<(PathBuf, usize)> {
+    let audio_file_size = audio.size();
 
-    match audio_path {
-        Some(path) => {
-            match metadata(&path) {
-                Ok(meta) => {
-                    let file_size_bytes = meta.len();
-                    audio_file_size = file_size_bytes as f64 / (1024.0 * 1024.0);
-                }
-                Err(..) => {
-                    audio_file_size = 0.0;
-                }
+    let input_file_size = match metadata(&input_file) {
+        Ok(meta) => ByteSize::from_bytes(meta.len()),
+        Err(e) => {
+            warn!("Can't read the size of {}: {}", input_file.display(), e);
+            ByteSize::default()
+        }
+    };
+
+    let input_context = format::input(&input_file).map_err(|source| DiscordifyError::Probe { path: input_file.clone(), source })?;
+
+    format::context::input::dump(&input_context, 0, input_file.to_str());
+
+    let duration_secs = match options.trim {
+        Some(range) => range.duration(),
+        None => input_context.duration() as f64 / f64::from(ffmpeg::ffi::AV_TIME_BASE),
+    };
+    if duration_secs.is_nan() || duration_secs <= 0.0 {
+        return Err(DiscordifyError::UnknownDuration(input_file));
+    }
+    let wanted_bytes = wanted_size.saturating_sub(audio_file_size).bytes();
+    if wanted_bytes == 0 {
+        return Err(DiscordifyError::SizeTargetUnreachable { wanted: wanted_size, audio: audio_file_size });
+    }
+    let wanted_bits = wanted_bytes as f64 * 8.0;
+    let cool_bit_rate = (wanted_bits / duration_secs) as usize;
+
+    let video_probe = probe(&input_context)?;
+    let input_codec_id = video_probe.codec_id;
+    drop(input_context);
+
+    let shape = options.output_shape(&video_probe, cool_bit_rate);
+    let (width, height) = (shape.width, shape.height);
+    if (width, height) != (video_probe.width, video_probe.height) {
+        info!("Scaling {}x{} down to {}x{} to fit the bitrate", video_probe.width, video_probe.height, width, height);
+    }
+    let frame_rate = if shape.frame_rate < video_probe.frame_rate * 0.99 {
+        info!("Dropping frame rate from {:.2} to {:.2} fps to fit the bitrate", video_probe.frame_rate, shape.frame_rate);
+        Some(shape.frame_rate)
+    } else {
+        None
+    };
+
+    let video_codec = options.resolved_codec();
+    let codec = match video_codec {
+        Some(video_codec) => video_codec
+            .encoder_names()
+            .iter()
+            .find_map(|name| encoder::find_by_name(name))
+            .ok_or_else(|| DiscordifyError::EncoderNotFound(video_codec.encoder_names().join("/")))?,
+        None => encoder::find(input_codec_id)
+            .ok_or_else(|| DiscordifyError::EncoderNotFound(format!("{:?}", input_codec_id)))?,
+    };
+
+    // The source profile keeps the input's codec, which the container may not take. Checked up
+    // front so a two-pass encode doesn't get through its analysis pass first.
+    let output_file = output_path
+        .to_str()
+        .ok_or_else(|| DiscordifyError::InvalidPath(output_path.clone()))?;
+    if let Some(container) = guess_format(output_file)
+        && !container_accepts(&container, codec.id())
+    {
+        return Err(DiscordifyError::IncompatibleCodec {
+            codec: codec.name().to_string(),
+            container: container.name().to_string(),
+        });
+    }
+
+    if options.profile == Profile::EmbedSafe && !video_codec::embeds_in_discord(codec.name()) {
+        warn!("{} output may not play inline in Discord", codec.name());
+    }
+
+    let mut opts_string = video_codec::encoder_options(codec.name(), input_file_size).to_string();
+    if options.profile == Profile::EmbedSafe {
+        let embed_opts = video_codec::embed_safe_options(codec.name());
+        if !embed_opts.is_empty() {
+            if !opts_string.is_empty() {
+                opts_string.push(',');
             }
+            opts_string.push_str(embed_opts);
         }
-        None => {}
     }
+    let encoder_opts = parse_opts(opts_string.clone())
+        .ok_or(DiscordifyError::InvalidOptions(opts_string))?;
+    let settings = EncodeSettings {
+        codec,
+        bit_rate: cool_bit_rate,
+        profile: options.profile,
+        width,
+        height,
+        frame_rate,
+        trim: options.trim,
+        duration: duration_secs,
+    };
 
-    let mut input_file_size = 0.0;
-    match metadata(&input_file) {
-        Ok(meta) => {
-            let file_size_bytes = meta.len();
-            input_file_size = file_size_bytes as f64 / (1024.0 * 1024.0);
+    match video_codec::two_pass(codec.name()) {
+        Some(two_pass) => {
+            // The encoder writes its first pass analysis to the stats file and reads it back
+            // in the second pass to hit the bitrate much more precisely.
+            let stats_path = output_path.with_extension("log");
+            let stats = stats_path
+                .to_str()
+                .ok_or_else(|| DiscordifyError::InvalidPath(stats_path.clone()))?;
+
+            let mut result = encode(&input_file, None, &output_path, settings, pass_opts(&encoder_opts, two_pass, Pass::First, stats), Pass::First, reporter, cancel);
+            if result.is_ok() {
+                result = match audio.finish() {
+                    Ok(audio_path) => encode(&input_file, audio_path, &output_path, settings, pass_opts(&encoder_opts, two_pass, Pass::Second, stats), Pass::Second, reporter, cancel),
+                    Err(e) => Err(e),
+                };
+            }
+            remove_stats_files(&stats_path);
+            result?;
         }
-        Err(e) => {
-            eprintln!("Error getting file metadata: {}", e);
+        None => {
+            let mut video_only_name = OsString::from("noaudio-");
+            video_only_name.push(output_path.file_name().unwrap_or_default());
+            let video_only_path = output_path.with_file_name(video_only_name);
+
+            let mut result = encode(&input_file, None, &video_only_path, settings, encoder_opts, Pass::Single, reporter, cancel);
+            if result.is_ok() {
+                result = match audio.finish() {
+                    Ok(Some(audio_path)) => mux(&video_only_path, audio_path, &output_path, options.profile, cancel),
+                    Ok(None) => fs::rename(&video_only_path, &output_path).map_err(Into::into),
+                    Err(e) => Err(e),
+                };
+            }
+            let _ = fs::remove_file(&video_only_path);
+            result?;
         }
     }
 
-    let output_file = output_path
-        .to_str()
-        .expect("failed to convert output file path to string");
+    Ok((output_path, cool_bit_rate))
+}
 
-    let audio_input_context: Option<format::context::input::Input>;
-    if let Some(path) = audio_path {
-        audio_input_context = format::input(path).ok();
-    } else {
-        audio_input_context = None;
+fn pass_opts<'a>(encoder_opts: &Dictionary<'a>, two_pass: TwoPass, pass: Pass, stats: &str) -> Dictionary<'a> {
+    let mut opts = encoder_opts.to_owned();
+    match two_pass {
+        TwoPass::X264 => opts.set("stats", stats),
+        TwoPass::X265 => {
+            let pass_number = if pass == Pass::First { 1 } else { 2 };
+            opts.set("x265-params", &format!("pass={pass_number}:stats={}", escape_x265_param(stats)));
+        }
     }
+    opts
+}
 
-    let mut input_context = format::input(&input_file).unwrap();
-    let mut output_context = format::output(&output_file).unwrap();
+// x265-params is split on ':' by av_get_token, which takes a backslash as an escape, so a path
+// like C:\clips\video.log survives as one value.
+fn escape_x265_param(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if matches!(c, ':' | '\\' | '\'') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
 
-    format::context::input::dump(&input_context, 0, Some(&input_file.to_str().expect("failed to convert input file path to string")));
+fn remove_stats_files(stats_path: &Path) {
+    for extension in ["log", "log.temp", "log.mbtree", "log.mbtree.temp", "log.cutree", "log.cutree.temp"] {
+        let _ = fs::remove_file(stats_path.with_extension(extension));
+    }
+}
 
-    let x264_opts_string = match input_file_size {
-        1.0..=200.0 => "preset=slow",
-        200.0..=500.0 => "preset=medium",
-        _ =>  "preset=fast"
-    };
+#[allow(clippy::too_many_arguments)]
+fn encode(
+    input_file: &Path,
+    audio_path: Option<&Path>,
+    output_path: &Path,
+    settings: EncodeSettings,
+    encoder_opts: Dictionary,
+    pass: Pass,
+    reporter: &Reporter,
+    cancel: &CancelToken,
+) -> Result<()> {
+    let output_file = output_path
+        .to_str()
+        .ok_or_else(|| DiscordifyError::InvalidPath(output_path.to_path_buf()))?;
 
-    let x264_opts = parse_opts(x264_opts_string.to_string())
-        .expect("invalid x264 options string");
+    let mut input_context = format::input(&input_file).map_err(|source| DiscordifyError::Probe { path: input_file.to_path_buf(), source })?;
+    if let Some(range) = &settings.trim {
+        trim::seek(&mut input_context, range)?;
+    }
+    // The first pass only has to produce the stats file, so its packets go nowhere.
+    let mut output_context = match pass {
+        Pass::First => format::output_as(&output_file, "null")?,
+        _ => format::output(&output_file)?,
+    };
 
-    let best_audio_index = match &audio_input_context {
-        Some(audio_ctx) => audio_ctx
-            .streams()
-            .best(media::Type::Audio)
-            .map(|stream| stream.index()),
+    let mut audio_input_context = match audio_path {
+        Some(path) => Some(format::input(&path).map_err(|source| DiscordifyError::Probe { path: path.to_path_buf(), source })?),
         None => None,
     };
+    let audio_stream_index = audio_input_context
+        .as_ref()
+        .and_then(|ctx| ctx.streams().best(media::Type::Audio).map(|stream| stream.index()));
 
     let best_video_stream_index = input_context
         .streams()
         .best(media::Type::Video)
-        .map(|stream| stream.index());
-    let mut stream_mapping: Vec<isize> = vec![0; input_context.nb_streams() as _];
+        .map(|stream| stream.index())
+        .ok_or(DiscordifyError::NoStream("video"))?;
+    let mut stream_mapping: Vec<isize> = vec![-1; input_context.nb_streams() as _];
     let mut input_stream_time_bases = vec![Rational(0, 0); input_context.nb_streams() as _];
-    let mut output_stream_time_bases = vec![Rational(0, 0); input_context.nb_streams() as _];
-    let mut transcoders = HashMap::new();
+    let mut transcoder = None;
     let mut output_stream_index = 0;
     for (input_stream_index, input_stream) in input_context.streams().enumerate() {
         let input_stream_medium = input_stream.parameters().medium();
-        if input_stream_medium != media::Type::Video && input_stream_medium != media::Type::Audio && input_stream_medium != media::Type::Subtitle
-        {
-            stream_mapping[input_stream_index] = -1;
+        if input_stream_index == best_video_stream_index {
+            transcoder = Some(VideoTranscoder::new(
+                &input_stream,
+                &mut output_context,
+                output_stream_index,
+                settings,
+                encoder_opts.to_owned(),
+                pass,
+                progress_tracker(reporter, pass, &settings, output_path),
+            )?);
+        } else if input_stream_medium == media::Type::Subtitle && pass != Pass::First && settings.profile == Profile::Source {
+            // Set up for stream copy for subtitles.
+            let mut output_stream = output_context.add_stream(encoder::find(codec::Id::None))?;
+            output_stream.set_parameters(input_stream.parameters());
+            unsafe {
+                (*output_stream.parameters().as_mut_ptr()).codec_tag = 0;
+            }
+        } else {
+            // Extra video streams (cover art etc.) are dropped, audio comes from the transcoded audio file.
+            // Subtitles only survive in the source profile, mp4 can't carry most of them anyway.
             continue;
         }
-        stream_mapping[input_stream_index] = output_stream_index;
+        stream_mapping[input_stream_index] = output_stream_index as isize;
         input_stream_time_bases[input_stream_index] = input_stream.time_base();
-
-        if input_stream_medium == media::Type::Video {
-            transcoders.insert(
-                input_stream_index,
-                VideoTranscoder::new(
-                    &input_stream,
-                    &mut output_context,
-                    output_stream_index as _,
-                    x264_opts.to_owned(),
-                    Some(input_stream_index) == best_video_stream_index,
-                    audio_file_size as f32,
-                    wanted_size,
-                    output_path.clone(),
-                    actual_start_time,
-                ).unwrap(),
-            );
-            }else if input_stream_medium == media::Type::Audio {
-                if let Some(audio_index) = best_audio_index {
-                    match &audio_input_context {
-                        Some(audio_input_context) => {
-                            let audio_stream = &audio_input_context.streams().nth(audio_index).unwrap();
-                            let mut output_audio_stream = output_context.add_stream(encoder::find(codec::Id::None)).unwrap();
-                            output_audio_stream.set_parameters(audio_stream.parameters());
-                            unsafe {
-                                (*output_audio_stream.parameters().as_mut_ptr()).codec_tag = 0;
-                            }
-                        }
-                        None => {
-                            eprintln!("No audio input context available, skipping audio stream.");
-                        }
-                    }
-                }
-            } else {
-                // Set up for stream copy for non-video stream (if applicable).
-                let mut output_stream = output_context.add_stream(encoder::find(codec::Id::None)).unwrap();
-                output_stream.set_parameters(input_stream.parameters());
-                unsafe {
-                    (*output_stream.parameters().as_mut_ptr()).codec_tag = 0;
-                }
-            }
-            output_stream_index += 1;
+        output_stream_index += 1;
+    }
+    let mut transcoder = transcoder.ok_or(DiscordifyError::NoStream("video"))?;
+
+    let mut audio_output_stream_index = None;
+    if let (Some(audio_input_context), Some(audio_index)) = (&audio_input_context, audio_stream_index) {
+        let audio_stream = audio_input_context.stream(audio_index).ok_or(DiscordifyError::NoStream("audio"))?;
+        let audio_codec_id = audio_stream.parameters().id();
+        if !container_accepts(&output_context.format(), audio_codec_id) {
+            return Err(DiscordifyError::IncompatibleCodec {
+                codec: audio_codec_id.name().to_string(),
+                container: output_context.format().name().to_string(),
+            });
         }
+        let mut output_audio_stream = output_context.add_stream(encoder::find(codec::Id::None))?;
+        output_audio_stream.set_parameters(audio_stream.parameters());
+        unsafe {
+            (*output_audio_stream.parameters().as_mut_ptr()).codec_tag = 0;
+        }
+        audio_output_stream_index = Some(output_stream_index);
+    }
 
     output_context.set_metadata(input_context.metadata().to_owned());
-    format::context::output::dump(&output_context, 0, Some(&output_file));
-    output_context.write_header().unwrap();
-
-    for (output_stream_index, _) in output_context.streams().enumerate() {
-        output_stream_time_bases[output_stream_index] = output_context.stream(output_stream_index as _).unwrap().time_base();
+    if pass != Pass::First {
+        format::context::output::dump(&output_context, 0, Some(&output_file));
     }
+    if pass != Pass::First && settings.profile == Profile::EmbedSafe {
+        // Put the moov atom first so Discord can start playing before the whole file is downloaded.
+        let mut muxer_opts = Dictionary::new();
+        muxer_opts.set("movflags", "+faststart");
+        output_context.write_header_with(muxer_opts)?;
+    } else {
+        output_context.write_header()?;
+    }
+
+    let output_stream_time_bases: Vec<Rational> = output_context
+        .streams()
+        .map(|stream| stream.time_base())
+        .collect();
+
+    let mut audio_packets = audio_input_context.as_mut().map(|ctx| ctx.packets().peekable());
 
     for (stream, mut packet) in input_context.packets() {
+        cancel.check()?;
         let input_stream_index = stream.index();
         let output_stream_index = stream_mapping[input_stream_index];
         if output_stream_index < 0 {
             continue;
         }
         let output_stream_time_base = output_stream_time_bases[output_stream_index as usize];
-        match transcoders.get_mut(&input_stream_index) {
-            Some(transcoder) => {
-                transcoder.send_packet_to_decoder(&packet);
-                transcoder.receive_and_process_decoded_frames(&mut output_context, output_stream_time_base);
+        let packet_secs = trim::seconds(packet.dts().or(packet.pts()), stream.time_base());
+        if input_stream_index == best_video_stream_index {
+            // dts never runs ahead of pts, so nothing decoded after this can land before the end.
+            if let (Some(range), Some(packet_secs)) = (&settings.trim, packet_secs)
+                && packet_secs >= range.end
+            {
+                break;
             }
-            None => {
-                // Do stream copy on non-video streams.
-                packet.rescale_ts(input_stream_time_bases[input_stream_index], output_stream_time_base);
-                packet.set_position(-1);
-                packet.set_stream(output_stream_index as _);
-                packet.write_interleaved(&mut output_context).unwrap();
+            transcoder.send_packet_to_decoder(&packet)?;
+            transcoder.receive_and_process_decoded_frames(&mut output_context, output_stream_time_base)?;
+
+            // Keep the audio roughly interleaved with the video we've read so far.
+            if let (Some(audio_packets), Some(audio_index), Some(audio_output_index)) = (audio_packets.as_mut(), audio_stream_index, audio_output_stream_index) {
+                // The audio file was trimmed to start at zero already.
+                let position = packet_secs.unwrap_or(0.0) - settings.trim.map_or(0.0, |range| range.start);
+                mux_audio_packets(audio_packets, audio_index, audio_output_index, output_stream_time_bases[audio_output_index], Some(position), &mut output_context)?;
             }
+        } else {
+            // Do stream copy on non-video streams.
+            if let Some(range) = &settings.trim {
+                if !packet_secs.is_some_and(|secs| range.contains(secs)) {
+                    continue;
+                }
+                let offset = range.start_in(input_stream_time_bases[input_stream_index]);
+                packet.set_pts(packet.pts().map(|pts| pts - offset));
+                packet.set_dts(packet.dts().map(|dts| dts - offset));
+            }
+            packet.rescale_ts(input_stream_time_bases[input_stream_index], output_stream_time_base);
+            packet.set_position(-1);
+            packet.set_stream(output_stream_index as _);
+            packet.write_interleaved(&mut output_context)?;
         }
     }
 
-    // Flush encoders and decoders.
-    for (output_stream_index, transcoder) in transcoders.iter_mut() {
-        let output_stream_time_base = output_stream_time_bases[*output_stream_index];
-        transcoder.send_eof_to_decoder();
-        transcoder.receive_and_process_decoded_frames(&mut output_context, output_stream_time_base);
-        transcoder.send_eof_to_encoder();
-        transcoder.receive_and_process_encoded_packets(&mut output_context, output_stream_time_base);
+    // Flush encoder and decoder.
+    let output_stream_time_base = output_stream_time_bases[transcoder.output_stream_index];
+    transcoder.send_eof_to_decoder()?;
+    transcoder.receive_and_process_decoded_frames(&mut output_context, output_stream_time_base)?;
+    transcoder.send_eof_to_encoder()?;
+    transcoder.receive_and_process_encoded_packets(&mut output_context, output_stream_time_base)?;
+
+    if let (Some(audio_packets), Some(audio_index), Some(audio_output_index)) = (audio_packets.as_mut(), audio_stream_index, audio_output_stream_index) {
+        mux_audio_packets(audio_packets, audio_index, audio_output_index, output_stream_time_bases[audio_output_index], None, &mut output_context)?;
     }
 
-    output_context.write_trailer().unwrap();
-    output_path
+    output_context.write_trailer()?;
+    Ok(())
+}
+
+// Puts the encoded audio next to the streams of a single-pass video output, copying both.
+fn mux(video_path: &Path, audio_path: &Path, output_path: &Path, profile: Profile, cancel: &CancelToken) -> Result<()> {
+    let output_file = output_path
+        .to_str()
+        .ok_or_else(|| DiscordifyError::InvalidPath(output_path.to_path_buf()))?;
+    let mut video_context = format::input(&video_path).map_err(|source| DiscordifyError::Probe { path: video_path.to_path_buf(), source })?;
+    let mut audio_context = format::input(&audio_path).map_err(|source| DiscordifyError::Probe { path: audio_path.to_path_buf(), source })?;
+    let mut output_context = format::output(&output_file)?;
+
+    let mut input_time_bases = Vec::new();
+    for input_stream in video_context.streams() {
+        let mut output_stream = output_context.add_stream(encoder::find(codec::Id::None))?;
+        output_stream.set_parameters(input_stream.parameters());
+        unsafe {
+            (*output_stream.parameters().as_mut_ptr()).codec_tag = 0;
+        }
+        input_time_bases.push(input_stream.time_base());
+    }
+    let audio_output_index = input_time_bases.len();
+    let audio_index = {
+        let audio_stream = audio_context.streams().best(media::Type::Audio).ok_or(DiscordifyError::NoStream("audio"))?;
+        let audio_codec_id = audio_stream.parameters().id();
+        if !container_accepts(&output_context.format(), audio_codec_id) {
+            return Err(DiscordifyError::IncompatibleCodec {
+                codec: audio_codec_id.name().to_string(),
+                container: output_context.format().name().to_string(),
+            });
+        }
+        let mut output_audio_stream = output_context.add_stream(encoder::find(codec::Id::None))?;
+        output_audio_stream.set_parameters(audio_stream.parameters());
+        unsafe {
+            (*output_audio_stream.parameters().as_mut_ptr()).codec_tag = 0;
+        }
+        audio_stream.index()
+    };
+
+    output_context.set_metadata(video_context.metadata().to_owned());
+    format::context::output::dump(&output_context, 0, Some(&output_file));
+    if profile == Profile::EmbedSafe {
+        let mut muxer_opts = Dictionary::new();
+        muxer_opts.set("movflags", "+faststart");
+        output_context.write_header_with(muxer_opts)?;
+    } else {
+        output_context.write_header()?;
+    }
+
+    let output_time_bases: Vec<Rational> = output_context
+        .streams()
+        .map(|stream| stream.time_base())
+        .collect();
+    let mut audio_packets = audio_context.packets().peekable();
+    for (stream, mut packet) in video_context.packets() {
+        cancel.check()?;
+        let index = stream.index();
+        let position = trim::seconds(packet.dts().or(packet.pts()), stream.time_base());
+        mux_audio_packets(&mut audio_packets, audio_index, audio_output_index, output_time_bases[audio_output_index], Some(position.unwrap_or(0.0)), &mut output_context)?;
+        packet.rescale_ts(input_time_bases[index], output_time_bases[index]);
+        packet.set_position(-1);
+        packet.set_stream(index);
+        packet.write_interleaved(&mut output_context)?;
+    }
+    mux_audio_packets(&mut audio_packets, audio_index, audio_output_index, output_time_bases[audio_output_index], None, &mut output_context)?;
+
+    output_context.write_trailer()?;
+    Ok(())
+}
+
+// The muxer ffmpeg picks for `path` by its extension.
+fn guess_format(path: &str) -> Option<format::Output> {
+    let path = CString::new(path).ok()?;
+    let container = unsafe { ffmpeg::ffi::av_guess_format(ptr::null(), path.as_ptr(), ptr::null()) };
+    (!container.is_null()).then(|| unsafe { format::Output::wrap(container as *mut _) })
+}
+
+// avformat_query_codec gives 1 when the muxer can store the codec, 0 when it can't and
+// a negative number when it doesn't know, in which case we let the muxer try.
+fn container_accepts(container: &format::Output, codec_id: codec::Id) -> bool {
+    unsafe {
+        ffmpeg::ffi::avformat_query_codec(
+            container.as_ptr(),
+            codec_id.into(),
+            codec::Compliance::Normal.into(),
+        ) != 0
+    }
+}
+
+// The analysis pass writes nothing worth measuring, the others write the output file.
+fn progress_tracker(reporter: &Reporter, pass: Pass, settings: &EncodeSettings, output_path: &Path) -> Tracker {
+    let start = settings.trim.map_or(0.0, |range| range.start);
+    match pass {
+        Pass::Single => reporter.tracker(Stage::Video, "VIDEO", start, settings.duration, Some(output_path.to_path_buf())),
+        Pass::First => reporter.tracker(Stage::Analyze, "VIDEO 1/2", start, settings.duration, None).followed_by(1),
+        Pass::Second => reporter.tracker(Stage::Video, "VIDEO 2/2", start, settings.duration, Some(output_path.to_path_buf())),
+    }
+}
+
+fn mux_audio_packets<'a, I: Iterator<Item = (format::stream::Stream<'a>, Packet)>>(
+    audio_packets: &mut Peekable<I>,
+    audio_stream_index: usize,
+    output_stream_index: usize,
+    output_stream_time_base: Rational,
+    until_secs: Option<f64>,
+    output_context: &mut format::context::Output,
+) -> Result<()> {
+    while let Some((stream, packet)) = audio_packets.peek() {
+        if let Some(until_secs) = until_secs {
+            let position = packet.dts().or(packet.pts()).unwrap_or(0) as f64 * f64::from(stream.time_base());
+            if position > until_secs {
+                break;
+            }
+        }
+        let Some((stream, mut packet)) = audio_packets.next() else {
+            break;
+        };
+        if stream.index() != audio_stream_index {
+            continue;
+        }
+        packet.rescale_ts(stream.time_base(), output_stream_time_base);
+        packet.set_position(-1);
+        packet.set_stream(output_stream_index);
+        packet.write_interleaved(output_context)?;
+    }
+    Ok(())
 }
 
 impl VideoTranscoder {
@@ -197,56 +605,51 @@ impl VideoTranscoder {
         input_stream: &format::stream::Stream,
         output_context: &mut format::context::Output,
         output_stream_index: usize,
-        x264_opts: Dictionary,
-        enable_logging: bool,
-        audio_file_size: f32,
-        wanted_size: &f32,
-        output_path : PathBuf,
-        actual_start_time:Instant,
-    ) -> Result<Self, ffmpeg::Error> {
+        settings: EncodeSettings,
+        encoder_opts: Dictionary,
+        pass: Pass,
+        progress: Tracker,
+    ) -> Result<Self> {
         let global_header = output_context.format().flags().contains(format::Flags::GLOBAL_HEADER);
         let decoder = codec::context::Context::from_parameters(input_stream.parameters())?
             .decoder()
             .video()?;
 
-        let codec = encoder::find(decoder.codec().unwrap().id());
+        let codec = settings.codec;
+        let bit_rate = settings.bit_rate;
         let mut output_stream = output_context.add_stream(codec)?;
 
-        let input_file:String;
-        if OVERRIDDEN_PATH.is_empty() {
-            input_file = env::args().nth(1).expect("missing input file");
-        } else {
-            input_file = OVERRIDDEN_PATH.to_string();
-        }
-        let input_context = format::input(&input_file)?;
-
-        let mut video_encoder = codec::context::Context::new_with_codec(codec.ok_or(ffmpeg::Error::InvalidData)?)
+        let mut video_encoder = codec::context::Context::new_with_codec(codec)
             .encoder()
             .video()?;
 
         output_stream.set_parameters(&video_encoder);
-        video_encoder.set_height(decoder.height());
-        video_encoder.set_width(decoder.width());
+        video_encoder.set_height(settings.height);
+        video_encoder.set_width(settings.width);
         video_encoder.set_aspect_ratio(decoder.aspect_ratio());
-        video_encoder.set_format(decoder.format());
-        video_encoder.set_frame_rate(decoder.frame_rate());
+        video_encoder.set_format(output_format(codec, decoder.format(), settings.profile)?);
+        match settings.frame_rate {
+            Some(frame_rate) => video_encoder.set_frame_rate(Some(Rational::from(frame_rate))),
+            None => video_encoder.set_frame_rate(decoder.frame_rate()),
+        }
         video_encoder.set_time_base(input_stream.time_base());
-        let wanted_mb:f64 = (wanted_size - audio_file_size) as f64;
-        let wanted_bytes:f64 = wanted_mb * 1024.0 * 1024.0;
-        let wanted_bits:f64 = wanted_bytes * 8.0;
-        let duration_secs = input_context.duration() as f64 / f64::from(ffmpeg::ffi::AV_TIME_BASE);
-        let cool_bit_rate:f64 = wanted_bits / duration_secs;
-
-        video_encoder.set_bit_rate(cool_bit_rate as usize);
-        video_encoder.set_max_bit_rate(cool_bit_rate as usize);
-        if global_header {
-            video_encoder.set_flags(codec::Flags::GLOBAL_HEADER);
+        video_encoder.set_bit_rate(bit_rate);
+        if video_codec::caps_max_bit_rate(codec.name()) {
+            video_encoder.set_max_bit_rate(bit_rate);
         }
 
-        let opened_video_encoder = video_encoder
-            .open_with(x264_opts)
-            .expect("error opening x264 with supplied settings");
+        let mut flags = codec::Flags::empty();
+        if global_header {
+            flags |= codec::Flags::GLOBAL_HEADER;
+        }
+        match pass {
+            Pass::First => flags |= codec::Flags::PASS1,
+            Pass::Second => flags |= codec::Flags::PASS2,
+            Pass::Single => {}
+        }
+        video_encoder.set_flags(flags);
 
+        let opened_video_encoder = video_encoder.open_with(encoder_opts)?;
 
         output_stream.set_parameters(&opened_video_encoder);
         Ok(Self {
@@ -254,101 +657,152 @@ impl VideoTranscoder {
             decoder,
             input_time_base: input_stream.time_base(),
             video_encoder: opened_video_encoder,
-            logging_enabled: enable_logging,
+            scaler: None,
+            frame_interval: settings.frame_rate.map(|frame_rate| 1.0 / frame_rate),
+            next_frame_time: None,
+            trim: settings.trim,
             frame_count: 0,
-            last_log_frame_count: 0,
-            starting_time: Instant::now(),
-            last_log_time: Instant::now(),
-            output_path,
-            wanted_size: *wanted_size,
-            actual_start_time,
+            progress,
         })
     }
 
-    fn send_packet_to_decoder(&mut self, packet: &Packet) {
-        self.decoder.send_packet(packet).unwrap();
+    fn send_packet_to_decoder(&mut self, packet: &Packet) -> Result<()> {
+        self.decoder.send_packet(packet)?;
+        Ok(())
     }
 
-    fn send_eof_to_decoder(&mut self) {
-        self.decoder.send_eof().unwrap();
+    fn send_eof_to_decoder(&mut self) -> Result<()> {
+        self.decoder.send_eof()?;
+        Ok(())
     }
 
     fn receive_and_process_decoded_frames(
         &mut self,
         output_context: &mut format::context::Output,
         output_stream_time_base: Rational,
-    ) {
+    ) -> Result<()> {
         let mut frame = frame::Video::empty();
         while self.decoder.receive_frame(&mut frame).is_ok() {
             self.frame_count += 1;
             let timestamp = frame.timestamp();
-            self.log_progress();
-            let file_size;
-            if fs::exists(&self.output_path).expect("failed to check if file exists") {
-                match metadata(&self.output_path) {
-                    Ok(meta) => {
-                        let file_size_bytes = meta.len();
-                        file_size = file_size_bytes as f64 / (1024.0 * 1024.0);
-                        if (file_size as f32) > self.wanted_size {
-                            break;
-                        }
-                    }
-                    Err(e) => {
-                        eprintln!("Error getting file metadata: {}", e);
-                    }
-                }
+            if !self.in_trim_range(timestamp) || !self.keep_frame(timestamp) {
+                continue;
+            }
+            self.progress.update(self.frame_count, trim::seconds(timestamp, self.input_time_base));
+            // Trimmed output starts at zero, like the trimmed audio.
+            let offset = self.trim.map_or(0, |range| range.start_in(self.input_time_base));
+            frame.set_pts(timestamp.map(|timestamp| timestamp - offset));
+            frame.set_kind(picture::Type::None);
+            if self.needs_conversion(&frame) {
+                let converted = self.convert_frame(&frame)?;
+                self.send_frame_to_encoder(&converted)?;
             } else {
-                println!("File does not exist: {}", self.output_path.display());
+                self.send_frame_to_encoder(&frame)?;
             }
+            self.receive_and_process_encoded_packets(output_context, output_stream_time_base)?;
+        }
+        Ok(())
+    }
 
-            frame.set_pts(timestamp);
-            frame.set_kind(picture::Type::None);
-            self.send_frame_to_encoder(&frame);
-            self.receive_and_process_encoded_packets(output_context, output_stream_time_base);
+    fn in_trim_range(&self, timestamp: Option<i64>) -> bool {
+        match (self.trim, trim::seconds(timestamp, self.input_time_base)) {
+            (Some(range), Some(secs)) => range.contains(secs),
+            _ => true,
+        }
+    }
+
+    // Drops frames to hit the reduced frame rate. Kept frames keep their original pts, so the
+    // timing (and audio sync) stays right even when the source is variable frame rate.
+    fn keep_frame(&mut self, timestamp: Option<i64>) -> bool {
+        let (Some(interval), Some(timestamp)) = (self.frame_interval, timestamp) else {
+            return true;
+        };
+        let time = timestamp as f64 * f64::from(self.input_time_base);
+        let next = self.next_frame_time.unwrap_or(time);
+        if time < next - interval * 0.01 {
+            return false;
+        }
+        // Skip whole intervals if the source had a gap, so we don't burst frames to catch up.
+        let skipped = ((time - next) / interval).floor().max(0.0);
+        self.next_frame_time = Some(next + (skipped + 1.0) * interval);
+        true
+    }
+
+    fn needs_conversion(&self, frame: &frame::Video) -> bool {
+        frame.format() != self.video_encoder.format()
+            || frame.width() != self.video_encoder.width()
+            || frame.height() != self.video_encoder.height()
+    }
+
+    fn convert_frame(&mut self, frame: &frame::Video) -> Result<frame::Video> {
+        let stale = match &self.scaler {
+            Some(scaler) => {
+                let input = scaler.input();
+                input.format != frame.format() || input.width != frame.width() || input.height != frame.height()
+            }
+            None => true,
+        };
+        if stale {
+            self.scaler = Some(scaling::Context::get(
+                frame.format(),
+                frame.width(),
+                frame.height(),
+                self.video_encoder.format(),
+                self.video_encoder.width(),
+                self.video_encoder.height(),
+                scaling::Flags::BICUBIC,
+            )?);
         }
+
+        let mut converted = frame::Video::empty();
+        if let Some(scaler) = self.scaler.as_mut() {
+            scaler.run(frame, &mut converted)?;
+        }
+        converted.set_pts(frame.pts());
+        converted.set_kind(picture::Type::None);
+        Ok(converted)
     }
 
-    fn send_frame_to_encoder(&mut self, frame: &frame::Video) {
-        self.video_encoder.send_frame(frame).unwrap();
+    fn send_frame_to_encoder(&mut self, frame: &frame::Video) -> Result<()> {
+        self.video_encoder.send_frame(frame)?;
+        Ok(())
     }
 
-    fn send_eof_to_encoder(&mut self) {
-        self.video_encoder.send_eof().unwrap();
+    fn send_eof_to_encoder(&mut self) -> Result<()> {
+        self.video_encoder.send_eof()?;
+        Ok(())
     }
 
     fn receive_and_process_encoded_packets(
         &mut self,
         output_context: &mut format::context::Output,
         output_stream_time_base: Rational,
-    ) {
+    ) -> Result<()> {
         let mut encoded = Packet::empty();
         while self.video_encoder.receive_packet(&mut encoded).is_ok() {
             encoded.set_stream(self.output_stream_index);
             encoded.rescale_ts(self.input_time_base, output_stream_time_base);
-            encoded.write_interleaved(output_context).unwrap();
+            encoded.write_interleaved(output_context)?;
         }
+        Ok(())
     }
+}
 
-    fn log_progress(&mut self) {
-        if !self.logging_enabled
-            || (self.frame_count - self.last_log_frame_count < 100
-            && self.last_log_time.elapsed().as_secs_f64() < 1.0)
-        {
-            return;
-        }
-
-        let total_seconds = self.actual_start_time.elapsed().as_secs_f64() as u64;
-        let minutes = (total_seconds % 3600) / 60;
-        let seconds = total_seconds % 60;
-        let formatted_time = format!("{:02}:{:02}", minutes, seconds);
-        eprintln!(
-            "[RUST] VIDEO ELAPSED: \t{:8.2}s\tFRAMES: {:8}\tTIMESTAMP: \t{formatted_time}",
-            self.starting_time.elapsed().as_secs_f64(),
-            self.frame_count,
-        );
-        self.last_log_frame_count = self.frame_count;
-        self.last_log_time = Instant::now();
+// The embed-safe profile always wants yuv420p, otherwise keep the input's format if the encoder takes it.
+fn output_format(codec: Codec, input_format: format::Pixel, profile: Profile) -> Result<format::Pixel> {
+    let supported: Vec<format::Pixel> = codec
+        .video()?
+        .formats()
+        .map(|formats| formats.collect())
+        .unwrap_or_default();
+    let wanted = match profile {
+        Profile::EmbedSafe => format::Pixel::YUV420P,
+        Profile::Source => input_format,
+    };
+    if supported.is_empty() || supported.contains(&wanted) {
+        return Ok(wanted);
     }
+    Ok(supported[0])
 }
 
 fn parse_opts<'a>(s: String) -> Option<Dictionary<'a>> {
@@ -361,4 +815,15 @@ fn parse_opts<'a>(s: String) -> Option<Dictionary<'a>> {
         }
     }
     Some(dict)
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn x265_param_escapes_separators() {
+        assert_eq!(escape_x265_param("/tmp/discordify-x/video.log"), "/tmp/discordify-x/video.log");
+        assert_eq!(escape_x265_param(r"C:\Temp\video.log"), r"C\:\\Temp\\video.log");
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::{HashMap, HashSet};
+use std::fs::{self, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::time::{Duration, Instant, UNIX_EPOCH};
+use notify::{RecursiveMode, Watcher};
+use tracing::{error, info, warn};
+use discordify::{ByteSize, CancelToken, DiscordifyError, TranscodeJob, TranscodeOptions};
+
+use crate::batch;
+use crate::cli::{ProgressFormat, WatchArgs};
+use crate::events;
+
+// Lives in the output folder, so pointing a second watcher at the same folders shares it.
+const RECORD_FILE: &str = ".discordify-processed";
+
+// One `<size> <mtime> <path>` line per processed file, so a recording that gets replaced
+// under the same name is picked up again.
+struct Record {
+    path: PathBuf,
+    seen: HashSet<String>,
+}
+
+impl Record {
+    fn load(path: PathBuf) -> io::Result<Self> {
+        let seen = match fs::read_to_string(&path) {
+            Ok(contents) => contents.lines().map(str::to_owned).collect(),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(Self { path, seen })
+    }
+
+    fn key(file: &Path) -> Option<String> {
+        let meta = fs::metadata(file).ok()?;
+        let modified = meta.modified().ok()?.duration_since(UNIX_EPOCH).ok()?.as_secs();
+        Some(format!("{} {} {}", meta.len(), modified, file.display()))
+    }
+
+    fn contains(&self, key: &str) -> bool {
+        self.seen.contains(key)
+    }
+
+    fn add(&mut self, key: String) -> io::Result<()> {
+        let mut file = OpenOptions::new().create(true).append(true).open(&self.path)?;
+        writeln!(file, "{key}")?;
+        self.seen.insert(key);
+        Ok(())
+    }
+}
+
+// A file counts as finished once its size has stopped changing for the settle time.
+struct Pending {
+    size: u64,
+    changed: Instant,
+}
+
+pub async fn run(args: &WatchArgs, cancel: &CancelToken) -> Result<(), DiscordifyError> {
+    let dir = fs::canonicalize(&args.dir)?;
+    let into = args.into.clone().unwrap_or_else(|| dir.join("discord"));
+    fs::create_dir_all(&into)?;
+    let into = fs::canonicalize(&into)?;
+    let filter = batch::Filter {
+        extensions: args.extensions.clone(),
+        glob: args.glob.clone(),
+        recursive: false,
+        naming: args.transcode.name.clone(),
+    };
+    let settle = Duration::from_secs(args.settle);
+    let presets = args.transcode.presets()?;
+    let target_size = args.transcode.target_size(&presets)?;
+    let options = TranscodeOptions {
+        cancel: cancel.clone(),
+        ..args.transcode.options(presets)
+    };
+    let mut record = Record::load(into.join(RECORD_FILE))?;
+
+    let (sender, receiver) = mpsc::channel();
+    let mut watcher = notify::recommended_watcher(sender).map_err(io::Error::other)?;
+    watcher.watch(&dir, RecursiveMode::NonRecursive).map_err(io::Error::other)?;
+
+    // Whatever landed while nobody was watching.
+    let mut pending: HashMap<PathBuf, Pending> = batch::collect_inputs(std::slice::from_ref(&dir), &filter)
+        .into_iter()
+        .map(|path| (path, Pending { size: 0, changed: Instant::now() }))
+        .collect();
+
+    info!("Watching {} for new recordings, results go to {}", dir.display(), into.display());
+
+    loop {
+        if cancel.is_cancelled() {
+            return Err(DiscordifyError::Cancelled);
+        }
+        match receiver.recv_timeout(Duration::from_secs(1)) {
+            Ok(Ok(event)) => {
+                for path in event.paths {
+                    if path.parent() == Some(dir.as_path()) && filter.matches(&path) {
+                        let entry = pending.entry(path).or_insert(Pending { size: 0, changed: Instant::now() });
+                        entry.changed = Instant::now();
+                    }
+                }
+            }
+            Ok(Err(e)) => warn!("Watch error: {}", e),
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => return Ok(()),
+        }
+
+        let mut ready = Vec::new();
+        pending.retain(|path, entry| {
+            let size = match fs::metadata(path) {
+                Ok(meta) if meta.is_file() => meta.len(),
+                // Deleted or renamed away before it settled.
+                _ => return false,
+            };
+            if size != entry.size {
+                entry.size = size;
+                entry.changed = Instant::now();
+                return true;
+            }
+            if entry.changed.elapsed() < settle {
+                return true;
+            }
+            ready.push(path.clone());
+            false
+        });
+
+        for path in ready {
+            let Some(key) = Record::key(&path) else {
+                continue;
+            };
+            if record.contains(&key) {
+                continue;
+            }
+            let json = args.transcode.progress == ProgressFormat::Json;
+            match process(&path, &into, target_size, &options, json).await {
+                Ok(output_path) => {
+                    record.add(key)?;
+                    if !args.transcode.no_clipboard {
+                        crate::utils::copy_video_files(&[output_path]);
+                    }
+                    if !args.transcode.no_sound {
+                        crate::play_done_sound();
+                    }
+                }
+                Err(e) => {
+                    if json {
+                        events::error(&path, &e.to_string());
+                    }
+                    error!("{}: {}", path.display(), e);
+                    if let DiscordifyError::Cancelled = e {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+async fn process(path: &Path, into: &Path, target_size: ByteSize, options: &TranscodeOptions, json: bool) -> Result<PathBuf, DiscordifyError> {
+    info!("New recording: {}", path.display());
+
+    let job = TranscodeJob::builder(path)
+        .target_size(target_size)
+        .options(TranscodeOptions {
+            output_dir: Some(into.to_path_buf()),
+            ..options.clone()
+        })
+        .build();
+    let output_path = job.output_path()?;
+    if json {
+        events::start(path, &output_path, target_size);
+    }
+    let result = job.run().await?;
+    info!("Wrote {} ({})", result.output_path.display(), result.size);
+    if json {
+        events::done(path, &result);
+    }
+    Ok(result.output_path)
+}